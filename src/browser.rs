@@ -1,10 +1,25 @@
 use anyhow::Result;
+use base64::{Engine as _, engine::general_purpose};
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
 use thirtyfour::{Proxy, extensions::cdp::ChromeDevTools, prelude::*};
 
-use crate::challenge::{self, ddos_guard};
-use crate::config::BrowserConfig;
+use crate::cdp_log::{self, RedirectHop};
+use crate::challenge::{self, ChallengeKind, ddos_guard, meta_refresh};
+use crate::config::{BrowserConfig, FallbackOrder, SameSiteDefault};
+use crate::cookie_file;
+use crate::data_store::{self, DataStore};
+use crate::driver_control::{self, DriverControl};
+use crate::inflight::{RequestControl, Stage};
+use crate::metrics::Metrics;
+use crate::scrappey::ScrappeyFailed;
+use crate::screenshots;
+use crate::state_transfer;
+use crate::trackers;
+use crate::user_agent;
+use crate::webhook::Engine;
 
 /// Stores browser session data such as user agent and cookies.
 /// This struct is serializable for persistence between runs.
@@ -12,13 +27,18 @@ use crate::config::BrowserConfig;
 pub struct BrowserData {
     pub user_agent: String,
     pub cookies: Vec<Cookie>,
+    /// When the data was last saved (Unix timestamp in seconds). Missing in files written by
+    /// older versions, in which case the file's modification time is used instead.
+    #[serde(default)]
+    pub saved_at: Option<i64>,
 }
 
 impl Default for BrowserData {
     fn default() -> Self {
         BrowserData {
-            user_agent: ua_generator::ua::spoof_ua().to_string(),
+            user_agent: user_agent::generate(),
             cookies: Vec::new(),
+            saved_at: None,
         }
     }
 }
@@ -30,12 +50,294 @@ pub struct Response {
     pub body: String,
     pub cookies: Vec<Cookie>,
     pub user_agent: String,
+    pub engine: Engine,
+    /// Challenge encountered on the way, if any.
+    pub challenge: Option<ChallengeKind>,
+    /// Browser console messages, only collected when requested.
+    pub console_logs: Vec<String>,
+    /// Responses the main document went through, only collected when requested.
+    pub redirect_chain: Vec<RedirectHop>,
+    /// MIME type of the returned document (e.g. `text/html`), if known.
+    pub content_type: Option<String>,
+    /// Base64-encoded PDF of the page, only rendered by the browser when requested.
+    pub pdf: Option<String>,
+    /// Base64-encoded PNG screenshot of the page, only taken by the browser when requested.
+    pub screenshot: Option<String>,
+    /// Visible text of the page, only collected when requested.
+    pub inner_text: Option<String>,
 }
 
+impl Response {
+    /// Which engine cleared the challenge, or `None` if there was no challenge to clear.
+    /// Scrappey always counts, as it also solves pages the browser never saw (Scrappey first).
+    pub fn solved_by(&self) -> SolvedBy {
+        match (self.challenge, self.engine) {
+            (_, Engine::Scrappey) => SolvedBy::Scrappey,
+            (None, _) => SolvedBy::None,
+            (Some(_), _) => SolvedBy::Browser,
+        }
+    }
+}
+
+/// Engine that cleared a challenge, as reported to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SolvedBy {
+    Browser,
+    Scrappey,
+    None,
+}
+
+/// Turn chromedriver's "session not created" error for a Chrome/chromedriver version mismatch into
+/// an actionable message. Returns None for any other error.
+fn version_mismatch(error: &str) -> Option<String> {
+    if !error.contains("session not created") {
+        return None;
+    }
+
+    // e.g. "This version of ChromeDriver only supports Chrome version 114
+    //       Current browser version is 116.0.5845.96 with binary path /usr/bin/google-chrome"
+    let version_after = |marker: &str| {
+        let start = error.find(marker)? + marker.len();
+        error[start..]
+            .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .next()
+            .filter(|version| !version.is_empty())
+            .map(str::to_string)
+    };
+    let driver_supports = version_after("only supports Chrome version ")?;
+    let browser_version = version_after("Current browser version is ");
+
+    Some(format!(
+        "Chrome and chromedriver versions are incompatible: chromedriver supports Chrome {driver_supports}, \
+         but the installed Chrome is {}. Install matching versions of both \
+         (rebuilding the Docker image updates them together).",
+        browser_version.as_deref().unwrap_or("a different version")
+    ))
+}
+
+/// Whether an error means the connection to chromedriver or Chrome was lost (e.g. one of them
+/// crashed), as opposed to the site or a challenge failing. Only the former is worth retrying.
+pub fn is_driver_connection_error(error: &anyhow::Error) -> bool {
+    use thirtyfour::error::WebDriverErrorInner;
+
+    error.chain().any(|cause| {
+        let Some(e) = cause.downcast_ref::<WebDriverError>() else {
+            return false;
+        };
+        match e.as_inner() {
+            WebDriverErrorInner::HttpError(_)
+            | WebDriverErrorInner::RequestFailed(_)
+            | WebDriverErrorInner::CommandSendError(_)
+            | WebDriverErrorInner::CommandRecvError(_)
+            | WebDriverErrorInner::InvalidSessionId(_) => true,
+            WebDriverErrorInner::UnknownError(info) | WebDriverErrorInner::NotInSpec(info) => {
+                let message = info.value.message.to_ascii_lowercase();
+                [
+                    "chrome not reachable",
+                    "disconnected",
+                    "session deleted",
+                    "tab crashed",
+                ]
+                .iter()
+                .any(|marker| message.contains(marker))
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Whether an error means Chrome itself (or its tab) crashed, usually for lack of memory, rather
+/// than chromedriver going away.
+pub fn is_browser_crash(error: &anyhow::Error) -> bool {
+    use thirtyfour::error::WebDriverErrorInner;
+
+    error.chain().any(|cause| {
+        let Some(e) = cause.downcast_ref::<WebDriverError>() else {
+            return false;
+        };
+        match e.as_inner() {
+            WebDriverErrorInner::UnknownError(info) | WebDriverErrorInner::NotInSpec(info) => {
+                let message = info.value.message.to_ascii_lowercase();
+                ["tab crashed", "page crash", "chrome not reachable"]
+                    .iter()
+                    .any(|marker| message.contains(marker))
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Whether an error means the target host could not be resolved (a typo or a dead domain), which
+/// no other engine or proxy will get past.
+/// Chrome reports it as a navigation error, as the host is resolved by the browser or its proxy.
+pub fn is_name_not_resolved(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let message = cause.to_string();
+        message.contains("ERR_NAME_NOT_RESOLVED") || message.contains("ERR_NAME_RESOLUTION_FAILED")
+    })
+}
+
+/// Whether an error means the browser couldn't connect through the upstream proxy, going by the
+/// network error Chrome reports for the navigation.
+pub fn is_proxy_error(error: &anyhow::Error) -> bool {
+    const PROXY_ERRORS: [&str; 4] = [
+        "ERR_PROXY_CONNECTION_FAILED",
+        "ERR_TUNNEL_CONNECTION_FAILED",
+        "ERR_SOCKS_CONNECTION_FAILED",
+        "ERR_PROXY_AUTH",
+    ];
+    error.chain().any(|cause| {
+        let message = cause.to_string();
+        PROXY_ERRORS.iter().any(|name| message.contains(name))
+    })
+}
+
+/// Whether an error means the request ran out of time: a challenge that didn't clear in time, a
+/// WebDriver page load or script timeout, or a Scrappey call that timed out.
+pub fn is_timeout(error: &anyhow::Error) -> bool {
+    use thirtyfour::error::WebDriverErrorInner;
+
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<SolveError>() {
+            return matches!(e, SolveError::Timeout(_));
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout();
+        }
+        cause.downcast_ref::<WebDriverError>().is_some_and(|e| {
+            matches!(
+                e.as_inner(),
+                WebDriverErrorInner::Timeout(_)
+                    | WebDriverErrorInner::ScriptTimeout(_)
+                    | WebDriverErrorInner::WebDriverTimeout(_)
+            )
+        })
+    })
+}
+
+/// A failure of the solver itself, as opposed to the WebDriver or Scrappey errors it passes on.
+#[derive(Debug)]
+pub enum SolveError {
+    /// A challenge didn't clear, or a page didn't load, within the request's timeout.
+    Timeout(String),
+    /// A Cloudflare challenge kept reloading into another challenge instead of the page.
+    ChallengeLoop(String),
+    /// The browser failed and so did the Scrappey fallback, for the reasons given.
+    AllEnginesFailed {
+        browser: anyhow::Error,
+        scrappey: anyhow::Error,
+    },
+}
+
+impl std::fmt::Display for SolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SolveError::Timeout(message) | SolveError::ChallengeLoop(message) => {
+                f.write_str(message)
+            }
+            SolveError::AllEnginesFailed { browser, scrappey } => write!(
+                f,
+                "All solving engines failed. Browser: {browser}. Scrappey: {scrappey}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+/// Page readiness to wait for after challenges clear, before the response is extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitUntil {
+    /// The HTML has been parsed (`document.readyState` is no longer `loading`).
+    DomContentLoaded,
+    /// The page and its subresources have loaded (`document.readyState` is `complete`).
+    Load,
+    /// The page has loaded and no new network requests have finished for a quiet period.
+    NetworkIdle,
+}
+
+impl std::str::FromStr for WaitUntil {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "domcontentloaded" => Ok(WaitUntil::DomContentLoaded),
+            "load" => Ok(WaitUntil::Load),
+            "networkidle" => Ok(WaitUntil::NetworkIdle),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Format of the solved page returned in addition to its HTML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Only the HTML.
+    #[default]
+    Html,
+    /// Also a PDF rendering of the page (browser engine only).
+    Pdf,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(OutputFormat::Html),
+            "pdf" => Ok(OutputFormat::Pdf),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Lower bound for the WebDriver page-load and script timeouts, even if the request budget is spent.
+const MIN_DRIVER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How many of the most recent console messages are appended to a failed request's error.
+const MAX_ERROR_CONSOLE_LOGS: usize = 20;
+
+/// How long the network must stay quiet for `WaitUntil::NetworkIdle` to be satisfied.
+const NETWORK_IDLE_QUIET_PERIOD: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How many more times a body shorter than `min_body_length` is read before the solve fails.
+const SHORT_BODY_RETRIES: u32 = 2;
+
+/// Delay before reading a too-short body again.
+const SHORT_BODY_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Most meta refresh redirects followed in a navigation, so that a page refreshing itself
+/// doesn't hold the request until it times out.
+const MAX_META_REFRESHES: u32 = 3;
+
 /// Main browser automation struct, encapsulating session data and configuration.
 pub struct Browser {
     pub data: BrowserData,
     pub config: BrowserConfig,
+    control: RequestControl,
+    wait_until: Option<WaitUntil>,
+    metrics: Arc<Metrics>,
+    /// Challenge detected during the current navigation.
+    challenge: Option<ChallengeKind>,
+    /// Whole timeout of the current request in seconds, which bounds a Scrappey solve.
+    request_timeout: u64,
+    raw_html: bool,
+    return_console_logs: bool,
+    return_redirect_chain: bool,
+    /// localStorage items set on the requested URL's origin before its scripts run.
+    local_storage: HashMap<String, String>,
+    /// Headers added to every request, over the configured ones.
+    request_headers: HashMap<String, String>,
+    return_screenshot: bool,
+    return_inner_text: bool,
+    /// Return the cookies of every domain, not only the current page's.
+    force_cookies: bool,
+    /// Follow `<meta http-equiv="refresh">` redirects before extracting the response.
+    follow_meta_refresh: bool,
+    /// Fall back to Scrappey when the browser can't solve a challenge.
+    scrappey_fallback: bool,
+    output_format: OutputFormat,
 }
 
 impl Browser {
@@ -44,6 +346,22 @@ impl Browser {
         Browser {
             data: BrowserData::default(),
             config: BrowserConfig::default(),
+            control: RequestControl::default(),
+            wait_until: None,
+            metrics: Arc::default(),
+            challenge: None,
+            request_timeout: 0,
+            raw_html: false,
+            return_console_logs: false,
+            return_redirect_chain: false,
+            local_storage: HashMap::new(),
+            request_headers: HashMap::new(),
+            return_screenshot: false,
+            return_inner_text: false,
+            force_cookies: false,
+            follow_meta_refresh: true,
+            scrappey_fallback: true,
+            output_format: OutputFormat::default(),
         }
     }
 
@@ -53,41 +371,399 @@ impl Browser {
         self
     }
 
+    /// Attach a request control handle, used to report progress and to abort the navigation.
+    pub fn with_control(mut self, control: RequestControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    /// Report Scrappey usage to a shared metrics registry.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Return the HTML as sent by the server instead of the rendered DOM.
+    pub fn with_raw_html(mut self, raw_html: bool) -> Self {
+        self.raw_html = raw_html;
+        self
+    }
+
+    /// Include the browser console messages in successful responses too, not just in errors.
+    pub fn with_console_logs(mut self, return_console_logs: bool) -> Self {
+        self.return_console_logs = return_console_logs;
+        self
+    }
+
+    /// Record the main document's redirects and return them with the response.
+    /// A redirect loop then fails the navigation with the loop in the error.
+    pub fn with_redirect_chain(mut self, return_redirect_chain: bool) -> Self {
+        self.return_redirect_chain = return_redirect_chain;
+        self
+    }
+
+    /// Seed the requested URL's localStorage with these items, in the browser and for Scrappey.
+    pub fn with_local_storage(mut self, local_storage: HashMap<String, String>) -> Self {
+        self.local_storage = local_storage;
+        self
+    }
+
+    /// Send these headers with every request the browser makes (and pass them to Scrappey).
+    /// They replace configured headers of the same name, such as `Accept-Language`.
+    pub fn with_request_headers(mut self, request_headers: HashMap<String, String>) -> Self {
+        self.request_headers = request_headers;
+        self
+    }
+
+    /// Include a screenshot of the solved page in the response.
+    pub fn with_screenshot(mut self, return_screenshot: bool) -> Self {
+        self.return_screenshot = return_screenshot;
+        self
+    }
+
+    /// Include the page's visible text (`document.body.innerText`) in the response.
+    pub fn with_inner_text(mut self, return_inner_text: bool) -> Self {
+        self.return_inner_text = return_inner_text;
+        self
+    }
+
+    /// Return every cookie in the browser, read over both the control protocol and WebDriver,
+    /// instead of only the current page's.
+    pub fn with_force_cookies(mut self, force_cookies: bool) -> Self {
+        self.force_cookies = force_cookies;
+        self
+    }
+
+    /// Whether to follow meta refresh redirects before extracting the response (on by default).
+    pub fn with_meta_refresh(mut self, follow_meta_refresh: bool) -> Self {
+        self.follow_meta_refresh = follow_meta_refresh;
+        self
+    }
+
+    /// Whether to fall back to Scrappey when the browser fails to solve a challenge (on by default).
+    /// When off, the browser gets the whole timeout instead of a third of it.
+    pub fn with_scrappey_fallback(mut self, scrappey_fallback: bool) -> Self {
+        self.scrappey_fallback = scrappey_fallback;
+        self
+    }
+
+    /// Also render the solved page in the given format.
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// Wait for the given page readiness before extracting the response.
+    pub fn with_wait_until(mut self, wait_until: Option<WaitUntil>) -> Self {
+        self.wait_until = wait_until;
+        self
+    }
+
     /// Load browser session data (user agent, cookies) from a JSON file.
+    /// Data older than the configured max age is treated as untrustworthy: its cookies are
+    /// discarded and, if configured, its user agent regenerated.
+    /// Does nothing if data persistence is disabled.
     pub fn load_data(&mut self, path: &str) -> Result<()> {
+        if !self.config.cookies.persist_data {
+            return Ok(());
+        }
         let file = std::fs::File::open(path)?;
+        let modified = file.metadata().and_then(|m| m.modified()).ok();
         let reader = std::io::BufReader::new(file);
         self.data = serde_json::from_reader(reader)?;
+
+        // Files from older versions don't record when they were saved
+        if self.data.saved_at.is_none() {
+            self.data.saved_at =
+                modified.map(|time| chrono::DateTime::<chrono::Utc>::from(time).timestamp());
+        }
+        self.discard_stale_data();
         Ok(())
     }
 
     /// Save browser session data (user agent, cookies) to a JSON file.
-    pub fn save_data(&self, path: &str) -> Result<()> {
-        let file = std::fs::File::create(path)?;
-        serde_json::to_writer_pretty(file, &self.data)?;
-        Ok(())
+    /// Does nothing if data persistence is disabled.
+    pub fn save_data(&mut self, path: &str) -> Result<()> {
+        if !self.config.cookies.persist_data {
+            return Ok(());
+        }
+        self.data.saved_at = Some(chrono::Utc::now().timestamp());
+        data_store::write_data(path, &self.data)
     }
 
-    /// Main navigation method: launches a browser, navigates to the URL, handles challenges, and extracts the response.
-    /// Ensures the driver is always quit, even on error.
+    /// Use persisted data that hasn't been written to disk yet, like `load_data` does with the file.
+    /// Does nothing if data persistence is disabled.
+    pub fn set_data(&mut self, data: BrowserData) {
+        if !self.config.cookies.persist_data {
+            return;
+        }
+        self.data = data;
+        self.discard_stale_data();
+    }
+
+    /// Hand browser session data to the store, to be written with its next flush.
+    /// Does nothing if data persistence is disabled.
+    pub fn defer_save(&mut self, store: &DataStore) {
+        if !self.config.cookies.persist_data {
+            return;
+        }
+        self.data.saved_at = Some(chrono::Utc::now().timestamp());
+        store.defer(self.data.clone());
+    }
+
+    /// Merge in the cookies other requests saved since this one loaded its data (the store's
+    /// pending data, or else the file), so that concurrent requests don't drop each other's
+    /// cookies. This request's cookies win over saved ones with the same name, domain and path.
+    /// Call it with the data lock held for writing, right before saving.
+    /// Does nothing if data persistence is disabled.
+    pub fn merge_saved_data(&mut self, store: &DataStore, path: &str) {
+        if !self.config.cookies.persist_data {
+            return;
+        }
+        let own = self.data.clone();
+        let loaded = match store.pending() {
+            Some(data) => {
+                self.set_data(data);
+                Ok(())
+            }
+            None => self.load_data(path),
+        };
+        let saved = std::mem::replace(&mut self.data, own);
+        match loaded {
+            Ok(()) => {
+                let cookies = std::mem::take(&mut self.data.cookies);
+                self.data.cookies = state_transfer::merge_cookies(saved.cookies, cookies);
+            }
+            Err(e) => debug!("No saved browser data to merge with: {e}"),
+        }
+    }
+
+    /// Replace the user agent with a newly generated one and return it. It is passed to Chrome when
+    /// the next session starts, so nothing from the old one (such as client hints) carries over.
+    pub fn rotate_user_agent(&mut self) -> String {
+        self.data.user_agent = user_agent::generate();
+        self.data.user_agent.clone()
+    }
+
+    /// Reset persisted data that exceeds the configured max age.
+    fn discard_stale_data(&mut self) {
+        let max_age_hours = self.config.cookies.max_data_age_hours;
+        let Some(saved_at) = self.data.saved_at else {
+            return;
+        };
+        let age_hours = (chrono::Utc::now().timestamp() - saved_at) / 3600;
+        if max_age_hours == 0 || age_hours < max_age_hours as i64 {
+            return;
+        }
+
+        info!(
+            "Persisted browser data is {age_hours} hours old (max {max_age_hours}), discarding its cookies"
+        );
+        self.data.cookies.clear();
+        if self.config.cookies.regenerate_stale_user_agent {
+            self.data.user_agent = user_agent::generate();
+        }
+    }
+
+    /// Main navigation method: solves the URL with the engines in the configured order.
+    /// With `FallbackOrder::ScrappeyFirst`, Scrappey gets two thirds of the timeout (or its own
+    /// configured timeout, if longer), and the browser is only launched (without falling back to Scrappey again) if it fails.
     pub async fn get(&mut self, url: &str, timeout: u64) -> Result<Response> {
+        self.request_timeout = timeout;
+        let scrappey_first = self.scrappey_fallback
+            && self.config.scrappey.fallback_order == FallbackOrder::ScrappeyFirst
+            && self.config.scrappey.is_configured();
+        if !scrappey_first {
+            return self.get_surviving_crash(url, timeout).await;
+        }
+
+        let start_time = std::time::Instant::now();
+        self.challenge = None;
+        self.control.check()?;
+        self.control.set_stage(Stage::Scrappey);
+        match self.fallback_to_scrappey(url, (timeout / 3) * 2).await {
+            Ok(Some(response)) => return Ok(response),
+            Ok(None) => {}
+            Err(e) => warn!("Scrappey failed to solve {url}, trying the browser: {e}"),
+        }
+
+        self.scrappey_fallback = false;
+        let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+        let result = self.get_surviving_crash(url, remaining).await;
+        self.scrappey_fallback = true;
+        result
+    }
+
+    /// Like `get_with_browser`, but if Chrome crashes, the navigation is retried once in a fresh
+    /// session (if configured) and then handed to Scrappey (if allowed), since a crash says
+    /// nothing about whether the browser could solve the page.
+    async fn get_surviving_crash(&mut self, url: &str, timeout: u64) -> Result<Response> {
+        let start_time = std::time::Instant::now();
+        let mut result = self.get_with_browser(url, timeout).await;
+        if let Err(e) = &result
+            && is_browser_crash(e)
+        {
+            warn!(
+                "Chrome crashed while solving {url}: {e}. This is usually a lack of resources: give \
+                 the container more memory, or send fewer requests at once"
+            );
+            if self.config.webdriver.retry_on_crash {
+                self.control.check()?;
+                info!("Retrying {url} once in a fresh browser session");
+                let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+                result = self.get_with_browser(url, remaining).await;
+            }
+        }
+
+        match result {
+            Err(crash)
+                if is_browser_crash(&crash)
+                    && self.scrappey_fallback
+                    && self.config.scrappey.is_configured() =>
+            {
+                self.control.check()?;
+                self.control.set_stage(Stage::ScrappeyFallback);
+                let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+                match self.fallback_to_scrappey(url, remaining).await {
+                    Ok(Some(response)) => Ok(response),
+                    Ok(None) => Err(crash),
+                    Err(scrappey_err) => Err(SolveError::AllEnginesFailed {
+                        browser: anyhow::anyhow!("Chrome crashed: {crash}"),
+                        scrappey: scrappey_err,
+                    }
+                    .into()),
+                }
+            }
+            result => result,
+        }
+    }
+
+    /// Launch a browser, navigate to the URL, handle challenges, and extract the response.
+    /// Ensures the driver is always quit, even on error.
+    async fn get_with_browser(&mut self, url: &str, timeout: u64) -> Result<Response> {
+        self.challenge = None;
+        let start_time = std::time::Instant::now();
+        self.control.check()?;
+        self.control.set_stage(Stage::LaunchingBrowser);
         let mut driver = self.setup_driver().await?;
 
         // Use a closure to ensure driver.quit() is always called
         let result = async {
-            self.configure_cookies(&driver).await?;
+            let webdriver_cookies = self.configure_cookies(&driver, url).await?;
+            self.configure_emulation(&driver, url).await?;
+
+            // A hung navigation or script should fail within the request's budget,
+            // not chromedriver's much longer defaults
+            let remaining = std::time::Duration::from_secs(timeout)
+                .saturating_sub(start_time.elapsed())
+                .max(MIN_DRIVER_TIMEOUT);
+            driver.set_page_load_timeout(remaining).await?;
+            driver.set_script_timeout(remaining).await?;
+
+            self.control.check()?;
+            self.control.set_stage(Stage::Navigating);
             driver.get(url).await?;
 
+            // WebDriver can only set cookies for the current page, so cookies the control
+            // protocol failed to set are added now, and the page reloaded to send them
+            if !webdriver_cookies.is_empty()
+                && self.add_webdriver_cookies(&driver, webdriver_cookies).await > 0
+            {
+                driver.refresh().await?;
+            }
+
             // Handle anti-bot challenges if present
             if let Some(response) = self.handle_challenges(&mut driver, url, timeout).await? {
                 return Ok(response);
             }
 
-            let response = self.extract_response(&driver, url).await?;
+            // Interstitials that redirect with a meta refresh don't look like challenges, and
+            // may lead to one
+            let mut refreshes = 0;
+            while self.follow_meta_refresh
+                && refreshes < MAX_META_REFRESHES
+                && let Some(refresh) = meta_refresh::detect(&driver).await
+            {
+                refreshes += 1;
+                let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+                meta_refresh::follow(
+                    &driver,
+                    &refresh,
+                    remaining,
+                    &self.config.webdriver.challenge,
+                    &self.control,
+                )
+                .await?;
+                let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+                if let Some(response) = self.handle_challenges(&mut driver, url, remaining).await? {
+                    return Ok(response);
+                }
+            }
+
+            // Give dynamic pages time to render before capturing them
+            if let Some(wait_until) = self.wait_until {
+                let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+                self.wait_for_page(&driver, wait_until, remaining).await?;
+            }
+
+            self.control.check()?;
+            self.control.set_stage(Stage::ExtractingResponse);
+            let mut response = self.extract_response(&driver, url).await?;
+            if self.output_format == OutputFormat::Pdf {
+                response.pdf = Some(self.print_to_pdf(&driver).await?);
+            }
+            // The page is solved, so a failed screenshot only loses the extra
+            if self.return_screenshot {
+                match self.take_screenshot(&driver).await {
+                    Ok(png) => response.screenshot = Some(general_purpose::STANDARD.encode(png)),
+                    Err(e) => warn!("Failed to take a screenshot of {url}: {e}"),
+                }
+            }
+            if self.return_inner_text {
+                match self.inner_text(&driver).await {
+                    Ok(text) => response.inner_text = Some(text),
+                    Err(e) => warn!("Failed to read the text of {url}: {e}"),
+                }
+            }
             Ok(response)
         }
         .await;
 
+        // Dump the recorded network events if tracing is enabled, and follow the redirects
+        let mut redirect_chain = Vec::new();
+        let mut result = result;
+        if self.config.webdriver.cdp_trace || self.return_redirect_chain {
+            let events = cdp_log::performance_events(&driver)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to read CDP events: {e}");
+                    Vec::new()
+                });
+            if self.config.webdriver.cdp_trace {
+                cdp_log::trace_network_events(&events);
+            }
+            if self.return_redirect_chain {
+                // A loop is reported instead of whatever error the browser gave up with
+                match cdp_log::redirect_chain(&events) {
+                    Ok(chain) => redirect_chain = chain,
+                    Err(e) => result = Err(e),
+                }
+            }
+        }
+
+        // The console often explains a failure (CSP errors, blocked scripts), so it's always
+        // collected then, and otherwise only when requested
+        let console_logs = if result.is_err() || self.return_console_logs {
+            cdp_log::console_messages(&driver)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to read browser console logs: {e}");
+                    Vec::new()
+                })
+        } else {
+            Vec::new()
+        };
+
         // Take screenshot on failure if enabled
         if result.is_err()
             && self.config.screenshots.capture_failure_screenshots
@@ -101,8 +777,23 @@ impl Browser {
 
         // Return the first error encountered, or the successful response
         match (result, quit_result) {
-            (Ok(response), Ok(_)) => Ok(response),
-            (Err(e), _) => Err(e),
+            (Ok(response), Ok(_)) => Ok(Response {
+                console_logs,
+                redirect_chain,
+                ..response
+            }),
+            (Err(e), _) if console_logs.is_empty() => Err(e),
+            (Err(e), _) => {
+                let skipped = console_logs.len().saturating_sub(MAX_ERROR_CONSOLE_LOGS);
+                // Kept as context, so that the error can still be classified by its cause
+                let message = format!(
+                    "{e}\nBrowser console (last {} of {} messages):\n{}",
+                    console_logs.len() - skipped,
+                    console_logs.len(),
+                    console_logs[skipped..].join("\n")
+                );
+                Err(e.context(message))
+            }
             (_, Err(e)) => Err(e.into()),
         }
     }
@@ -121,41 +812,248 @@ impl Browser {
         caps.add_arg("--disable-infobars")?;
         caps.insert_browser_option("excludeSwitches", ["enable-automation"])?;
 
-        // Always use the local proxy bridge (noauth) for outgoing requests
+        // Match the browser UI language (and navigator.languages) to the spoofed locale
+        if let Some(locale) = self.config.locale.locale() {
+            caps.add_arg(&format!("--lang={locale}"))?;
+        }
+        if let Some(accept_language) = &self.config.locale.accept_language {
+            caps.add_experimental_option(
+                "prefs",
+                serde_json::json!({ "intl.accept_languages": accept_language }),
+            )?;
+        }
+
+        // Record console output in chromedriver's browser log, and CDP events in its
+        // performance log when tracing or following redirects
+        let mut logging_prefs = serde_json::json!({ "browser": "ALL" });
+        if self.config.webdriver.cdp_trace || self.return_redirect_chain {
+            logging_prefs["performance"] = "ALL".into();
+        }
+        caps.insert_base_capability("goog:loggingPrefs".to_string(), logging_prefs);
+
+        // Chrome can't authenticate to proxies, so HTTP proxies are reached through the local
+        // proxy bridge (noauth), while unauthenticated SOCKS5 proxies are used directly
+        let proxy = &self.config.proxy;
+        let (http_proxy, socks_proxy) = if proxy.is_direct() {
+            (None, Some(format!("{}:{}", proxy.host, proxy.port)))
+        } else {
+            let bridge = &self.config.bridge;
+            let addr = std::net::SocketAddr::new(bridge.connect_address(), bridge.port);
+            (Some(addr.to_string()), None)
+        };
         caps.set_proxy(Proxy::Manual {
             ftp_proxy: None,
-            http_proxy: Some("127.0.0.1:8080".to_string()),
+            socks_version: socks_proxy.as_ref().map(|_| 5),
+            http_proxy,
             ssl_proxy: None,
-            socks_proxy: None,
-            socks_version: None,
+            socks_proxy,
             socks_username: None, // unsupported in chromedriver
             socks_password: None, // unsupported in chromedriver
             no_proxy: None,
         })?;
 
-        let driver = WebDriver::new(&self.config.webdriver.url, caps).await?;
+        let driver = WebDriver::new(&self.config.webdriver.url, caps)
+            .await
+            .map_err(|e| match version_mismatch(&e.to_string()) {
+                Some(message) => anyhow::anyhow!(message),
+                None => e.into(),
+            })?;
         Ok(driver)
     }
 
     /// Set cookies in the browser using Chrome DevTools Protocol.
-    /// Cleans expired cookies before setting.
-    async fn configure_cookies(&mut self, driver: &WebDriver) -> Result<()> {
+    /// Cleans expired cookies before setting, then adds the cookies file's cookies for the URL's
+    /// domain, which take precedence over stored ones and are persisted along with them afterwards.
+    async fn configure_cookies(&mut self, driver: &WebDriver, url: &str) -> Result<Vec<Cookie>> {
         self.clean_expired_cookies();
 
+        let mut cookies = self.data.cookies.clone();
+        if let Some(path) = &self.config.cookies.cookies_file {
+            // Re-read on every request so that edits apply without a restart
+            match cookie_file::load(path) {
+                Ok(seeded) => cookies.extend(cookie_file::for_url(seeded, url)),
+                Err(e) => warn!("{e}"),
+            }
+        }
+
+        self.set_cookies(driver, &cookies).await
+    }
+
+    /// Set cookies in the browser. Cookies the browser rejects are only logged.
+    /// If the control protocol fails, the cookies are returned for `add_webdriver_cookies`, as
+    /// WebDriver can only set cookies for the current page's domain, so once it is loaded.
+    async fn set_cookies(&self, driver: &WebDriver, cookies: &[Cookie]) -> Result<Vec<Cookie>> {
+        let cookies: Vec<Cookie> = cookies
+            .iter()
+            .map(|cookie| self.with_default_same_site(cookie))
+            .collect();
+        match self.control(driver).set_cookies(&cookies).await {
+            Ok(()) => Ok(Vec::new()),
+            Err(e) => {
+                warn!("Failed to set cookies over CDP, falling back to WebDriver: {e}");
+                Ok(cookies)
+            }
+        }
+    }
+
+    /// Set cookies over WebDriver, on the current page. Returns how many were set; cookies for
+    /// other domains are rejected and logged.
+    async fn add_webdriver_cookies(&self, driver: &WebDriver, cookies: Vec<Cookie>) -> usize {
+        let mut added = 0;
+        for cookie in cookies {
+            let name = cookie.name.clone();
+            match driver.add_cookie(cookie).await {
+                Ok(()) => added += 1,
+                Err(e) => warn!("WebDriver could not set cookie '{name}': {e}"),
+            }
+        }
+        added
+    }
+
+    /// All cookies in the browser. If the control protocol fails, falls back to WebDriver, which
+    /// only returns the current page's cookies.
+    async fn get_cookies(&self, driver: &WebDriver) -> Result<Vec<Cookie>> {
+        match self.control(driver).get_cookies().await {
+            Ok(cookies) => Ok(cookies),
+            Err(e) => {
+                warn!("Failed to read cookies over CDP, falling back to WebDriver: {e}");
+                Ok(driver.get_all_cookies().await?)
+            }
+        }
+    }
+
+    /// Apply the configured SameSite default to a cookie that doesn't specify one.
+    /// Chrome treats such cookies as `Lax`, which breaks clearance cookies needed cross-site.
+    fn with_default_same_site(&self, cookie: &Cookie) -> Cookie {
+        let mut cookie = cookie.clone();
+        if cookie.same_site.is_none() {
+            cookie.same_site = match self.config.cookies.default_same_site {
+                SameSiteDefault::NoneIfSecure if cookie.secure == Some(true) => {
+                    Some(SameSite::None)
+                }
+                SameSiteDefault::NoneIfSecure | SameSiteDefault::Unset => None,
+                SameSiteDefault::Lax => Some(SameSite::Lax),
+                SameSiteDefault::Strict => Some(SameSite::Strict),
+            };
+        }
+        cookie
+    }
+
+    /// Apply the preload script, locale/timezone overrides, domain blocking and extra request headers via Chrome DevTools Protocol.
+    async fn configure_emulation(&self, driver: &WebDriver, url: &str) -> Result<()> {
         let dev_tools = ChromeDevTools::new(driver.handle.clone());
-        dev_tools.execute_cdp("Network.enable").await?;
 
-        for cookie in &self.data.cookies {
-            let cookie_value = serde_json::to_value(cookie)
-                .map_err(|e| anyhow::anyhow!("Failed to serialize cookie: {}", e))?;
+        // Runs on every new document before the site's own scripts
+        if let Some(script) = &self.config.webdriver.preload_script {
+            dev_tools
+                .execute_cdp_with_params(
+                    "Page.addScriptToEvaluateOnNewDocument",
+                    serde_json::json!({ "source": script }),
+                )
+                .await?;
+        }
+
+        // localStorage belongs to an origin, so it can only be written once a document of that
+        // origin exists: the items are set at the start of each of its documents instead
+        if !self.local_storage.is_empty() {
+            let origin = url::Url::parse(url)?.origin().ascii_serialization();
+            let script = format!(
+                "if (location.origin === {}) {{ \
+                   for (const [key, value] of Object.entries({})) {{ \
+                     try {{ localStorage.setItem(key, value); }} catch (e) {{}} \
+                   }} \
+                 }}",
+                serde_json::Value::String(origin),
+                serde_json::to_string(&self.local_storage)?
+            );
+            dev_tools
+                .execute_cdp_with_params(
+                    "Page.addScriptToEvaluateOnNewDocument",
+                    serde_json::json!({ "source": script }),
+                )
+                .await?;
+        }
+
+        if let Some(locale) = self.config.locale.locale() {
+            dev_tools
+                .execute_cdp_with_params(
+                    "Emulation.setLocaleOverride",
+                    serde_json::json!({ "locale": locale }),
+                )
+                .await?;
+        }
+        if let Some(timezone) = &self.config.locale.timezone {
+            dev_tools
+                .execute_cdp_with_params(
+                    "Emulation.setTimezoneOverride",
+                    serde_json::json!({ "timezoneId": timezone }),
+                )
+                .await?;
+        }
+
+        if !self.config.webdriver.blocked_domains.is_empty() {
+            dev_tools.execute_cdp("Network.enable").await?;
+            dev_tools
+                .execute_cdp_with_params(
+                    "Network.setBlockedURLs",
+                    serde_json::json!({
+                        "urls": trackers::url_patterns(&self.config.webdriver.blocked_domains)
+                    }),
+                )
+                .await?;
+        }
+
+        let headers = self.extra_headers(url);
+        if !headers.is_empty() {
             dev_tools
-                .execute_cdp_with_params("Network.setCookie", cookie_value)
+                .execute_cdp_with_params(
+                    "Network.setExtraHTTPHeaders",
+                    serde_json::json!({ "headers": headers }),
+                )
                 .await?;
         }
 
         Ok(())
     }
 
+    /// Run the configured solve script for the URL's host, if any. A failing script fails the
+    /// request, as it is usually needed to get past the challenge.
+    async fn run_solve_script(&self, driver: &WebDriver, url: &str) -> Result<()> {
+        let Some(solve_script) = self.config.webdriver.solve_script_for(url) else {
+            return Ok(());
+        };
+        debug!("Running the solve script for {}", solve_script.host);
+        driver
+            .execute(&solve_script.script, vec![])
+            .await
+            .map_err(|e| anyhow::anyhow!("Solve script for {} failed: {e}", solve_script.host))?;
+        Ok(())
+    }
+
+    /// Headers added to every request the browser makes.
+    /// `Network.setExtraHTTPHeaders` replaces the whole set, so all extra headers are collected here.
+    fn extra_headers(&self, url: &str) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        if let Some(accept_language) = &self.config.locale.accept_language {
+            headers.insert("Accept-Language".to_string(), accept_language.clone());
+        }
+        if let Some(tag) = &self.config.webdriver.tag_header {
+            if tag.applies_to(url) {
+                headers.insert(tag.name.clone(), tag.value.clone());
+            } else {
+                debug!(
+                    "Not sending the {} tag header to {url} (excluded host)",
+                    tag.name
+                );
+            }
+        }
+        for (name, value) in &self.request_headers {
+            headers.retain(|existing, _| !existing.eq_ignore_ascii_case(name));
+            headers.insert(name.clone(), value.clone());
+        }
+        headers
+    }
+
     /// Remove expired cookies from the session data.
     fn clean_expired_cookies(&mut self) {
         let now = chrono::Utc::now().timestamp();
@@ -171,6 +1069,8 @@ impl Browser {
     }
 
     /// Detect and handle anti-bot challenges (DDoS Guard, Cloudflare).
+    /// Some sites chain several challenges, so detection is repeated after each solve until the
+    /// page is unprotected, the configured number of rounds is used up, or the timeout expires.
     /// Returns a Response if solved by fallback, otherwise None.
     async fn handle_challenges(
         &mut self,
@@ -178,41 +1078,231 @@ impl Browser {
         url: &str,
         timeout: u64,
     ) -> Result<Option<Response>> {
-        // Handle DDoS Guard challenge if detected
-        if ddos_guard::is_protected(driver).await {
-            info!("DDoS Guard challenge detected, handling...");
-            ddos_guard::handle_challenge(driver, timeout).await?;
+        let start_time = std::time::Instant::now();
+        let max_rounds = self.config.webdriver.max_challenge_rounds.max(1);
+
+        // On slow connections the challenge page may not have set its title yet
+        let grace = std::time::Duration::from_millis(self.config.webdriver.challenge_grace_ms)
+            .min(std::time::Duration::from_secs(timeout) / 10);
+        if !grace.is_zero() {
+            tokio::time::sleep(grace).await;
         }
 
-        // Handle Cloudflare challenge if detected
-        if challenge::cloudflare::is_protected(driver).await {
-            info!("Cloudflare challenge detected, handling...");
-            if let Some(response) = self
-                .handle_cloudflare_challenge(driver, url, timeout)
+        for round in 1..=max_rounds {
+            self.run_solve_script(driver, url).await?;
+            let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+            let mut solved_any = false;
+
+            let challenge_config = self.config.webdriver.challenge.clone();
+
+            // Handle DDoS Guard challenge if detected
+            if ddos_guard::is_protected(driver, &challenge_config).await {
+                info!("DDoS Guard challenge detected (round {round}), handling...");
+                self.challenge = Some(ChallengeKind::DdosGuard);
+                self.control.set_stage(Stage::SolvingChallenge);
+                ddos_guard::handle_challenge(driver, remaining, &challenge_config, &self.control)
+                    .await?;
+                solved_any = true;
+            }
+
+            // Handle Cloudflare challenge if detected
+            if challenge::cloudflare::is_protected(driver, &challenge_config).await {
+                info!("Cloudflare challenge detected (round {round}), handling...");
+                self.challenge = Some(ChallengeKind::Cloudflare);
+                self.control.set_stage(Stage::SolvingChallenge);
+                let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+                if let Some(response) = self
+                    .handle_cloudflare_challenge(driver, url, remaining, ChallengeKind::Cloudflare)
+                    .await?
+                {
+                    return Ok(Some(response));
+                }
+                solved_any = true;
+            }
+
+            // Handle a Turnstile widget on the page itself, once any interstitial is passed
+            if challenge::turnstile::is_protected(driver, &challenge_config).await {
+                info!("Turnstile widget detected (round {round}), handling...");
+                self.challenge = Some(ChallengeKind::Turnstile);
+                self.control.set_stage(Stage::SolvingChallenge);
+                let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+                if let Some(response) = self
+                    .handle_cloudflare_challenge(driver, url, remaining, ChallengeKind::Turnstile)
+                    .await?
+                {
+                    return Ok(Some(response));
+                }
+                solved_any = true;
+            }
+
+            // Only once the top-level page is clear, as a challenge page has no content iframes
+            if !solved_any && self.config.webdriver.check_iframes {
+                let remaining = timeout.saturating_sub(start_time.elapsed().as_secs());
+                if let Some(kind) = challenge::iframe::handle_challenges(
+                    driver,
+                    remaining,
+                    &challenge_config,
+                    &self.control,
+                )
                 .await?
-            {
-                return Ok(Some(response));
+                {
+                    self.challenge = Some(kind);
+                }
+            }
+
+            if !solved_any {
+                return Ok(None);
+            }
+            if start_time.elapsed().as_secs() >= timeout {
+                break;
             }
         }
 
+        // Don't return a challenge page as the result
+        let challenge_config = &self.config.webdriver.challenge;
+        if ddos_guard::is_protected(driver, challenge_config).await
+            || challenge::cloudflare::is_protected(driver, challenge_config).await
+            || challenge::turnstile::is_protected(driver, challenge_config).await
+        {
+            return Err(anyhow::anyhow!(
+                "Page is still protected after {max_rounds} challenge rounds"
+            ));
+        }
         Ok(None)
     }
 
-    /// Attempt to solve Cloudflare challenge, falling back to Scrappey if needed.
+    /// Attempt to solve a Cloudflare challenge (the interstitial or a Turnstile widget), falling
+    /// back to Scrappey if needed.
+    /// If both fail, the returned error reports why each engine failed.
     async fn handle_cloudflare_challenge(
         &mut self,
         driver: &mut WebDriver,
         url: &str,
         timeout: u64,
+        kind: ChallengeKind,
     ) -> Result<Option<Response>> {
-        match challenge::cloudflare::handle_challenge(driver, timeout / 3).await {
+        let browser_timeout = if self.scrappey_fallback {
+            timeout / 3
+        } else {
+            timeout
+        };
+        let challenge_config = &self.config.webdriver.challenge;
+        let result = match kind {
+            ChallengeKind::Turnstile => {
+                challenge::turnstile::handle_challenge(
+                    driver,
+                    browser_timeout,
+                    challenge_config,
+                    &self.control,
+                )
+                .await
+            }
+            _ => {
+                challenge::cloudflare::handle_challenge(
+                    driver,
+                    browser_timeout,
+                    challenge_config,
+                    &self.control,
+                )
+                .await
+            }
+        };
+        match result {
             Ok(_) => {
-                info!("Cloudflare challenge handled successfully.");
+                info!("{kind:?} challenge handled successfully.");
                 Ok(None)
             }
+            // A crash is retried in a fresh session before Scrappey is tried, and an unresolvable
+            // host isn't worth a credit
+            Err(browser_err)
+                if !self.scrappey_fallback
+                    || is_browser_crash(&browser_err)
+                    || is_name_not_resolved(&browser_err) =>
+            {
+                Err(browser_err)
+            }
+            Err(browser_err) => {
+                // Don't spend Scrappey credits on a request the client has abandoned
+                self.control.check()?;
+                warn!("Failed to handle {kind:?} challenge: {browser_err}");
+                self.control.set_stage(Stage::ScrappeyFallback);
+                let response = self
+                    .fallback_to_scrappey(url, (timeout / 3) * 2)
+                    .await
+                    .map_err(|scrappey_err| SolveError::AllEnginesFailed {
+                        browser: browser_err,
+                        scrappey: scrappey_err,
+                    })?;
+
+                match response {
+                    Some(response) if self.config.scrappey.reverify => {
+                        Ok(Some(self.reverify_scrappey(driver, url, response).await))
+                    }
+                    response => Ok(response),
+                }
+            }
+        }
+    }
+
+    /// Reload the page in the browser with the cookies and user agent of a Scrappey solve, and
+    /// return the browser's response if the page is no longer protected. Otherwise (or on any
+    /// error), Scrappey's response is returned as is.
+    /// The browser response still counts as solved by Scrappey, since its cookies cleared the
+    /// challenge, and like them is only persisted if `persist_cookies` is set.
+    async fn reverify_scrappey(
+        &mut self,
+        driver: &mut WebDriver,
+        url: &str,
+        scrappey_response: Response,
+    ) -> Response {
+        info!("Re-verifying the Scrappey solve of {url} in the browser...");
+        let data = self.data.clone();
+        let result = async {
+            // Clearance cookies are bound to the user agent that earned them
+            self.control(driver)
+                .set_user_agent(&scrappey_response.user_agent)
+                .await?;
+            // The browser is still on the page, so WebDriver can set its cookies if needed
+            let webdriver_cookies = self.set_cookies(driver, &scrappey_response.cookies).await?;
+            self.add_webdriver_cookies(driver, webdriver_cookies).await;
+            driver.goto(url).await?;
+
+            let grace = std::time::Duration::from_millis(self.config.webdriver.challenge_grace_ms);
+            tokio::time::sleep(grace).await;
+            let challenge_config = &self.config.webdriver.challenge;
+            if challenge::cloudflare::is_protected(driver, challenge_config).await
+                || ddos_guard::is_protected(driver, challenge_config).await
+            {
+                return Ok(None);
+            }
+
+            self.data.user_agent = scrappey_response.user_agent.clone();
+            self.extract_response(driver, url).await.map(Some)
+        }
+        .await;
+
+        if !self.config.scrappey.persist_cookies {
+            self.data = data;
+        }
+        match result {
+            Ok(Some(response)) => {
+                info!("Browser confirmed the Scrappey solve of {url}");
+                Response {
+                    engine: Engine::Scrappey,
+                    ..response
+                }
+            }
+            Ok(None) => {
+                warn!(
+                    "Page is still protected in the browser with Scrappey's cookies, returning Scrappey's response"
+                );
+                scrappey_response
+            }
             Err(e) => {
-                warn!("Failed to handle Cloudflare challenge: {e}");
-                self.fallback_to_scrappey(url, (timeout / 3) * 2).await
+                warn!(
+                    "Failed to re-verify the Scrappey solve in the browser, returning Scrappey's response: {e}"
+                );
+                scrappey_response
             }
         }
     }
@@ -221,37 +1311,91 @@ impl Browser {
     /// Updates cookies and user agent from Scrappey response.
     async fn fallback_to_scrappey(&mut self, url: &str, timeout: u64) -> Result<Option<Response>> {
         if !self.config.scrappey.is_configured() {
-            return Err(anyhow::anyhow!("Scrappey API key not configured"));
+            return Err(ScrappeyFailed(anyhow::anyhow!("Scrappey API key not configured")).into());
         }
+        // Scrappey's solves are slow, so they aren't cut off by a small share of the budget
+        let timeout = self
+            .config
+            .scrappey
+            .solve_timeout(timeout, self.request_timeout);
 
         // Build proxy string for Scrappey
         let proxy = self.config.proxy.to_url();
 
+        // A hard ceiling on credit spend, however many requests come in
+        let scrappey = &self.config.scrappey;
+        if !self.metrics.reserve_scrappey_call(
+            scrappey.max_calls_per_window,
+            std::time::Duration::from_secs(scrappey.rate_window_secs),
+        ) {
+            return Err(ScrappeyFailed(anyhow::anyhow!(
+                "Scrappey fallback rate limit reached ({} calls per {}s), not spending a credit",
+                scrappey.max_calls_per_window,
+                scrappey.rate_window_secs
+            ))
+            .into());
+        }
+
         info!("Attempting to resolve challenge with Scrappey... (this may take 20-40 seconds)");
 
+        self.metrics.record_scrappey_call(url);
         let response = challenge::cloudflare::scrappey_resolve(
             url.to_string(),
-            self.config.scrappey.api_key.clone(),
+            &self.config.scrappey,
             &proxy,
+            &self.local_storage,
+            &self.request_headers,
             timeout,
         )
-        .await?;
+        .await
+        .inspect_err(|_| self.metrics.record_scrappey_failure())
+        .map_err(ScrappeyFailed)?;
 
         info!("Scrappey resolved the challenge successfully.");
-        debug!("Scrappey response: {response:?}");
-
-        // Update cookies from Scrappey response
-        if let Some(cookies) = response.solution.cookies {
-            for cookie in cookies {
-                self.data.cookies.push(cookie.into());
-            }
+        if self.config.logging.bodies {
+            debug!(
+                "Scrappey response: {:?}",
+                response.for_log(self.config.logging.body_max_chars)
+            );
         }
 
-        // Update user agent from Scrappey response
-        if let Some(ua) = response.solution.user_agent {
-            self.data.user_agent = ua;
+        let scrappey_cookies: Vec<Cookie> = response
+            .solution
+            .cookies
+            .unwrap_or_default()
+            .into_iter()
+            .map(Cookie::from)
+            .collect();
+        let user_agent = response
+            .solution
+            .user_agent
+            .unwrap_or_else(|| self.data.user_agent.clone());
+
+        // The response always carries Scrappey's cookies and UA, since the client needs them as a pair.
+        // Scrappey's cookies replace stored ones with the same name, domain and path.
+        let cookies = state_transfer::merge_cookies(self.data.cookies.clone(), scrappey_cookies);
+
+        // Only persist them for later browser attempts when explicitly enabled (see ScrappeyConfig::persist_cookies)
+        if self.config.scrappey.persist_cookies {
+            self.data.cookies = cookies.clone();
+            self.data.user_agent = user_agent.clone();
+        } else {
+            debug!("Not persisting Scrappey cookies for browser reuse");
         }
 
+        let content_type = response
+            .solution
+            .response_headers
+            .as_ref()
+            .and_then(|headers| {
+                headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            })
+            .and_then(|(_, value)| value.as_str())
+            .map(str::to_string);
+
+        let inner_text = response.solution.inner_text;
         Ok(Some(Response {
             url: response
                 .solution
@@ -259,48 +1403,219 @@ impl Browser {
                 .unwrap_or_else(|| url.to_string()),
             status: response.solution.status_code.unwrap_or(200),
             body: response.solution.response.unwrap_or_default(),
-            cookies: self.data.cookies.clone(),
-            user_agent: self.data.user_agent.clone(),
+            cookies,
+            user_agent,
+            engine: Engine::Scrappey,
+            challenge: self.challenge,
+            console_logs: Vec::new(),
+            redirect_chain: Vec::new(),
+            content_type,
+            pdf: None,
+            screenshot: None,
+            inner_text: inner_text.filter(|_| self.return_inner_text),
         }))
     }
 
-    /// Extract the final response from the browser, including cookies and page source.
-    async fn extract_response(&mut self, driver: &WebDriver, url: &str) -> Result<Response> {
-        let dev_tools = ChromeDevTools::new(driver.handle.clone());
+    /// Poll the page until it reaches the requested readiness, or the timeout expires.
+    /// Timing out is not an error: the response is extracted as-is, with a warning.
+    async fn wait_for_page(
+        &self,
+        driver: &WebDriver,
+        wait_until: WaitUntil,
+        timeout: u64,
+    ) -> Result<()> {
+        let start_time = std::time::Instant::now();
+        let poll_interval = std::time::Duration::from_millis(100);
+        // Finished resource count and when it last changed, for the network idle check
+        let mut last_activity: Option<(u64, std::time::Instant)> = None;
 
-        // Extract cookies using Chrome DevTools
-        let new_cookies = dev_tools
-            .execute_cdp("Storage.getCookies")
-            .await?
-            .get("cookies")
-            .and_then(|c| c.as_array())
-            .map_or(Vec::new(), |arr| {
-                arr.iter()
-                    .filter_map(|c| serde_json::from_value(c.clone()).ok())
-                    .collect::<Vec<Cookie>>()
-            });
+        loop {
+            self.control.check()?;
+            if start_time.elapsed().as_secs() > timeout {
+                warn!("Timed out waiting for page readiness ({wait_until:?}), extracting anyway");
+                return Ok(());
+            }
+
+            let ready_state = driver
+                .execute("return document.readyState;", vec![])
+                .await?
+                .convert::<String>()
+                .unwrap_or_default();
+
+            let ready = match wait_until {
+                WaitUntil::DomContentLoaded => ready_state != "loading",
+                WaitUntil::Load => ready_state == "complete",
+                WaitUntil::NetworkIdle if ready_state == "complete" => {
+                    let finished = driver
+                        .execute(
+                            // Lift the default 250-entry cap so a busy page can't look idle
+                            "performance.setResourceTimingBufferSize(100000); \
+                             return performance.getEntriesByType('resource').length;",
+                            vec![],
+                        )
+                        .await?
+                        .convert::<u64>()
+                        .unwrap_or_default();
+                    match last_activity {
+                        Some((count, since)) if count == finished => {
+                            since.elapsed() >= NETWORK_IDLE_QUIET_PERIOD
+                        }
+                        _ => {
+                            last_activity = Some((finished, std::time::Instant::now()));
+                            false
+                        }
+                    }
+                }
+                WaitUntil::NetworkIdle => false,
+            };
+
+            if ready {
+                debug!(
+                    "Page reached {wait_until:?} after {}ms",
+                    start_time.elapsed().as_millis()
+                );
+                return Ok(());
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
 
-        self.data.cookies = new_cookies;
+    /// Extract the final response from the browser, including cookies and page source.
+    async fn extract_response(&mut self, driver: &WebDriver, url: &str) -> Result<Response> {
+        // Cookies of every domain, not only the current page's, so that they can be persisted
+        self.data.cookies = self.get_cookies(driver).await?;
 
-        let body = driver.source().await?;
-        let cookies = driver.get_all_cookies().await?;
+        let body = if self.raw_html {
+            match self.fetch_raw_html(driver).await {
+                Ok(html) => html,
+                Err(e) => {
+                    warn!("Failed to fetch raw HTML, returning the rendered DOM instead: {e}");
+                    driver.source().await?
+                }
+            }
+        } else {
+            self.rendered_source(driver, url).await?
+        };
+        let mut cookies = driver.get_all_cookies().await?;
+        if self.force_cookies {
+            // The control protocol's cookies have every domain's, and more complete attributes
+            cookies = state_transfer::merge_cookies(cookies, self.data.cookies.clone());
+        }
+        let status = self.navigation_status(driver).await;
+        let content_type = driver
+            .execute("return document.contentType;", vec![])
+            .await
+            .ok()
+            .and_then(|ret| ret.convert::<String>().ok());
 
         Ok(Response {
             url: url.to_string(),
-            status: 200, // thirtyfour doesn't provide status, assuming success
+            status,
             body,
             cookies,
             user_agent: self.data.user_agent.clone(),
+            engine: Engine::Browser,
+            challenge: self.challenge,
+            console_logs: Vec::new(),
+            redirect_chain: Vec::new(),
+            content_type,
+            pdf: None,
+            screenshot: None,
+            inner_text: None,
         })
     }
 
+    /// Read the rendered DOM. A body shorter than `min_body_length` usually means that the
+    /// extraction raced the page's rendering, so it is read again a few times before giving up.
+    async fn rendered_source(&self, driver: &WebDriver, url: &str) -> Result<String> {
+        let min_length = self.config.webdriver.min_body_length;
+        let mut body = driver.source().await?;
+        for attempt in 1..=SHORT_BODY_RETRIES {
+            if body.len() >= min_length {
+                return Ok(body);
+            }
+            info!(
+                "Body of {url} is only {} bytes (MIN_BODY_LENGTH is {min_length}), reading it again \
+                 ({attempt}/{SHORT_BODY_RETRIES})",
+                body.len()
+            );
+            self.control.check()?;
+            tokio::time::sleep(SHORT_BODY_RETRY_DELAY).await;
+            body = driver.source().await?;
+        }
+        if body.len() < min_length {
+            return Err(anyhow::anyhow!(
+                "Body of {url} is still only {} bytes after {SHORT_BODY_RETRIES} retries \
+                 (MIN_BODY_LENGTH is {min_length})",
+                body.len()
+            ));
+        }
+        Ok(body)
+    }
+
+    /// Read the visible text of the current page, as rendered (without hidden elements).
+    async fn inner_text(&self, driver: &WebDriver) -> Result<String> {
+        let text = driver
+            .execute(
+                "return document.body ? document.body.innerText : '';",
+                vec![],
+            )
+            .await?
+            .convert::<String>()?;
+        Ok(text)
+    }
+
+    /// Render the current page as a PDF via Chrome DevTools Protocol, returned base64-encoded.
+    async fn print_to_pdf(&self, driver: &WebDriver) -> Result<String> {
+        let dev_tools = ChromeDevTools::new(driver.handle.clone());
+        let result = dev_tools
+            .execute_cdp_with_params(
+                "Page.printToPDF",
+                serde_json::json!({ "printBackground": true }),
+            )
+            .await?;
+        result["data"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow::anyhow!("Chrome returned no PDF data"))
+    }
+
+    /// Fetch the current URL again from within the page to get the HTML exactly as the server
+    /// sends it, before any scripts modify it. Uses the browser's cookies, so challenges stay cleared.
+    async fn fetch_raw_html(&self, driver: &WebDriver) -> Result<String> {
+        let html = driver
+            .execute(
+                "return fetch(location.href, { credentials: 'include' }).then(r => r.text());",
+                vec![],
+            )
+            .await?
+            .convert::<String>()?;
+        Ok(html)
+    }
+
+    /// Read the HTTP status of the main document.
+    /// WebDriver doesn't expose it, so fall back to assuming success if unavailable.
+    async fn navigation_status(&self, driver: &WebDriver) -> u16 {
+        self.control(driver)
+            .status()
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or(200)
+    }
+
+    /// Control operations for the driver, over Chrome DevTools Protocol.
+    fn control(&self, driver: &WebDriver) -> driver_control::Cdp {
+        driver_control::Cdp::new(driver)
+    }
+
     /// Capture a screenshot when challenge resolution fails for debugging purposes.
     async fn capture_failure_screenshot(&self, driver: &WebDriver, url: &str) -> Result<()> {
         // Create screenshot directory if it doesn't exist
         std::fs::create_dir_all(&self.config.screenshots.screenshot_dir)?;
 
         // Clean up old screenshots first
-        self.cleanup_old_screenshots()?;
+        screenshots::cleanup(&self.config.screenshots)?;
 
         // Generate filename with timestamp and domain
         let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
@@ -311,75 +1626,15 @@ impl Browser {
         let filepath = std::path::Path::new(&self.config.screenshots.screenshot_dir).join(filename);
 
         // Take screenshot
-        let screenshot_data = driver.screenshot_as_png().await?;
+        let screenshot_data = self.take_screenshot(driver).await?;
         std::fs::write(&filepath, screenshot_data)?;
 
         info!("Failure screenshot saved to: {}", filepath.display());
         Ok(())
     }
 
-    /// Clean up old failure screenshots, keeping only the N most recent ones.
-    fn cleanup_old_screenshots(&self) -> Result<()> {
-        let screenshot_dir = std::path::Path::new(&self.config.screenshots.screenshot_dir);
-
-        // If directory doesn't exist, nothing to clean up
-        if !screenshot_dir.exists() {
-            return Ok(());
-        }
-
-        // Get all failure screenshot files
-        let mut screenshot_files: Vec<_> = std::fs::read_dir(screenshot_dir)?
-            .filter_map(|entry| {
-                let entry = entry.ok()?;
-                let path = entry.path();
-
-                // Only consider PNG files that start with "failure_"
-                if path.is_file()
-                    && path.extension().and_then(|s| s.to_str()) == Some("png")
-                    && path
-                        .file_name()
-                        .and_then(|s| s.to_str())
-                        .is_some_and(|name| name.starts_with("failure_"))
-                {
-                    // Get the modification time for sorting
-                    let metadata = entry.metadata().ok()?;
-                    let modified = metadata.modified().ok()?;
-                    Some((path, modified))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        // Sort by modification time (newest first)
-        screenshot_files.sort_by(|a, b| b.1.cmp(&a.1));
-
-        // Remove old screenshots if we exceed the limit
-        if screenshot_files.len() > self.config.screenshots.max_failure_screenshots {
-            let files_to_remove =
-                &screenshot_files[self.config.screenshots.max_failure_screenshots..];
-
-            for (file_path, _) in files_to_remove {
-                if let Err(e) = std::fs::remove_file(file_path) {
-                    warn!(
-                        "Failed to remove old screenshot {}: {}",
-                        file_path.display(),
-                        e
-                    );
-                } else {
-                    debug!("Removed old screenshot: {}", file_path.display());
-                }
-            }
-
-            if !files_to_remove.is_empty() {
-                info!(
-                    "Cleaned up {} old failure screenshots, keeping {} most recent",
-                    files_to_remove.len(),
-                    self.config.screenshots.max_failure_screenshots
-                );
-            }
-        }
-
-        Ok(())
+    /// PNG screenshot of the visible part of the page, at the configured window size.
+    async fn take_screenshot(&self, driver: &WebDriver) -> Result<Vec<u8>> {
+        Ok(driver.screenshot_as_png().await?)
     }
 }
@@ -0,0 +1,105 @@
+//! In-memory cache of solved responses.
+//! Clients often request the same protected URL several times within seconds; serving those
+//! from the cache avoids launching a browser (and possibly spending Scrappey credits) each time.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::flaresolverr::ChallengeResolutionResult;
+
+struct CacheEntry {
+    solution: ChallengeResolutionResult,
+    stored_at: Instant,
+}
+
+/// Thread-safe TTL cache of solutions, keyed by normalized URL and proxy.
+/// Expired entries are evicted lazily, on lookup and when inserting.
+pub struct ResponseCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl ResponseCache {
+    /// Create a cache holding up to `max_entries` solutions for `ttl` each.
+    /// A zero TTL or size disables caching.
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            max_entries,
+        }
+    }
+
+    /// Returns true if caching is enabled.
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero() && self.max_entries > 0
+    }
+
+    /// Build the cache key for a request.
+    /// The URL is normalized (lowercase scheme/host, default port and fragment dropped) so that
+    /// equivalent URLs share an entry. Raw HTML and rendered DOM responses are cached separately.
+    pub fn key(url: &str, proxy: Option<&str>, raw_html: bool) -> String {
+        let url = match url::Url::parse(url) {
+            Ok(mut parsed) => {
+                parsed.set_fragment(None);
+                parsed.to_string()
+            }
+            Err(_) => url.to_string(),
+        };
+        format!(
+            "{url}|{}|{}",
+            proxy.unwrap_or_default(),
+            if raw_html { "raw" } else { "dom" }
+        )
+    }
+
+    /// Look up a fresh cached solution.
+    pub fn get(&self, key: &str) -> Option<ChallengeResolutionResult> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.stored_at.elapsed() < self.ttl => Some(entry.solution.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Drop every cached solution, e.g. once they were solved with a user agent no longer in use.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Store a solution, evicting expired entries and then the oldest one if the cache is full.
+    pub fn insert(&self, key: String, solution: ChallengeResolutionResult) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.stored_at.elapsed() < self.ttl);
+
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key,
+            CacheEntry {
+                solution,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+}
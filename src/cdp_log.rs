@@ -0,0 +1,187 @@
+//! Access to Chrome DevTools Protocol events recorded by chromedriver.
+//! thirtyfour only exposes CDP commands, not events, so events are read back from
+//! chromedriver's `performance` log, and console output from its `browser` log
+//! (both enabled via the `goog:loggingPrefs` capability).
+
+use anyhow::Result;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use thirtyfour::{RequestData, SessionId, WebDriver, common::command::FormatRequestData};
+
+/// Chromedriver log types that can be retrieved.
+#[derive(Debug)]
+enum LogCommand {
+    /// Fetch (and clear) all buffered entries of the given log type.
+    Get(&'static str),
+}
+
+impl FormatRequestData for LogCommand {
+    fn format_request(&self, session_id: &SessionId) -> RequestData {
+        match self {
+            LogCommand::Get(log_type) => RequestData::new(
+                reqwest::Method::POST,
+                format!("/session/{session_id}/se/log"),
+            )
+            .add_body(json!({ "type": log_type })),
+        }
+    }
+}
+
+/// A raw chromedriver log entry.
+#[derive(Debug, Clone, Deserialize)]
+struct LogEntry {
+    #[serde(default)]
+    level: String,
+    message: String,
+}
+
+/// A single CDP event, e.g. `Network.responseReceived`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CdpEvent {
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// Most hops returned in a redirect chain. Longer chains are cut off, as they're almost always
+/// loops that the browser gave up on.
+const MAX_REDIRECT_CHAIN: usize = 30;
+
+/// A response the main document went through: a redirect, or the page that was finally loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectHop {
+    pub url: String,
+    pub status: u16,
+}
+
+/// Envelope chromedriver wraps each performance log message in.
+#[derive(Debug, Deserialize)]
+struct PerformanceMessage {
+    message: CdpEvent,
+}
+
+/// Fetch all CDP events recorded since the last call.
+/// Requires the session to have been created with performance logging enabled.
+pub async fn performance_events(driver: &WebDriver) -> Result<Vec<CdpEvent>> {
+    let entries: Vec<LogEntry> = driver
+        .handle
+        .cmd(LogCommand::Get("performance"))
+        .await?
+        .value()?;
+
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| serde_json::from_str::<PerformanceMessage>(&entry.message).ok())
+        .map(|m| m.message)
+        .collect())
+}
+
+/// Fetch all browser console messages recorded since the last call, formatted as `[LEVEL] message`.
+/// Chromedriver fills this log from `Runtime.consoleAPICalled` and `Log.entryAdded`, so it includes
+/// page `console.*` calls as well as browser-reported errors such as CSP violations and blocked
+/// or failed resources.
+/// Requires the session to have been created with browser logging enabled.
+pub async fn console_messages(driver: &WebDriver) -> Result<Vec<String>> {
+    let entries: Vec<LogEntry> = driver
+        .handle
+        .cmd(LogCommand::Get("browser"))
+        .await?
+        .value()?;
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| format!("[{}] {}", entry.level, entry.message))
+        .collect())
+}
+
+/// Log the key network events (requests, responses, failures) at debug level.
+pub fn trace_network_events(events: &[CdpEvent]) {
+    for event in events {
+        let params = &event.params;
+        match event.method.as_str() {
+            "Network.requestWillBeSent" => debug!(
+                "CDP {} {} {}",
+                event.method,
+                params["request"]["method"].as_str().unwrap_or("?"),
+                params["request"]["url"].as_str().unwrap_or("?")
+            ),
+            "Network.responseReceived" => debug!(
+                "CDP {} {} {}",
+                event.method,
+                params["response"]["status"],
+                params["response"]["url"].as_str().unwrap_or("?")
+            ),
+            "Network.loadingFailed" => debug!(
+                "CDP {} {} (request {})",
+                event.method,
+                params["errorText"].as_str().unwrap_or("?"),
+                params["requestId"].as_str().unwrap_or("?")
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// The main document's responses in order, redirects included, from the recorded CDP events.
+/// A reload after a challenge starts a new navigation, whose responses are appended.
+/// Fails if a navigation redirects back to a URL it already went through.
+pub fn redirect_chain(events: &[CdpEvent]) -> Result<Vec<RedirectHop>> {
+    let mut main_frame = None;
+    let mut chain = Vec::new();
+    // A navigation keeps its request ID across redirects
+    let mut visited: HashSet<(&str, &str)> = HashSet::new();
+
+    for event in events {
+        let params = &event.params;
+        if params["type"] != "Document" {
+            continue;
+        }
+        // The first document is the page's own; later ones on other frames are iframes
+        let frame = params["frameId"].as_str();
+        if main_frame.is_none() {
+            main_frame = frame;
+        }
+        if frame != main_frame {
+            continue;
+        }
+        let response = match event.method.as_str() {
+            "Network.requestWillBeSent" => &params["redirectResponse"],
+            "Network.responseReceived" => &params["response"],
+            _ => continue,
+        };
+        let (Some(url), Some(status)) = (response["url"].as_str(), response["status"].as_f64())
+        else {
+            continue;
+        };
+
+        if event.method == "Network.requestWillBeSent"
+            && !visited.insert((params["requestId"].as_str().unwrap_or_default(), url))
+        {
+            let start = chain
+                .iter()
+                .rposition(|hop: &RedirectHop| hop.url == url)
+                .unwrap_or_default();
+            let hops: Vec<&str> = chain[start..]
+                .iter()
+                .map(|hop: &RedirectHop| hop.url.as_str())
+                .chain([url])
+                .collect();
+            return Err(anyhow::anyhow!(
+                "Redirect loop detected: {}",
+                hops.join(" -> ")
+            ));
+        }
+        if chain.len() == MAX_REDIRECT_CHAIN {
+            warn!("Redirect chain longer than {MAX_REDIRECT_CHAIN} responses, cutting it off");
+            break;
+        }
+        chain.push(RedirectHop {
+            url: url.to_string(),
+            status: status as u16,
+        });
+    }
+
+    Ok(chain)
+}
@@ -1,23 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+/// Kind of anti-bot challenge encountered during a navigation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChallengeKind {
+    Cloudflare,
+    DdosGuard,
+    Turnstile,
+}
+
+/// Identifies the current document: the time its navigation started, which changes whenever
+/// the page is reloaded or navigates away.
+async fn document_id(driver: &thirtyfour::WebDriver) -> Option<String> {
+    driver
+        .execute("return String(performance.timeOrigin);", vec![])
+        .await
+        .ok()?
+        .convert::<String>()
+        .ok()
+}
+
 /// DDoS-Guard challenge detection and handling logic.
 pub mod ddos_guard {
     use anyhow::Result;
 
+    use super::ChallengeKind;
+    use crate::browser::SolveError;
+    use crate::config::ChallengeConfig;
+    use crate::inflight::RequestControl;
+
+    /// Default title fragment of the DDoS-Guard challenge page.
+    pub const CHALLENGE_TITLE: &str = "DDoS-Guard";
+
     /// Returns true if the current page is protected by DDoS-Guard.
-    pub async fn is_protected(driver: &mut thirtyfour::WebDriver) -> bool {
-        driver
-            .title()
-            .await
-            .is_ok_and(|title| title.contains("DDoS-Guard"))
+    /// Always false if DDoS-Guard detection is disabled, without reading the title.
+    pub async fn is_protected(
+        driver: &mut thirtyfour::WebDriver,
+        config: &ChallengeConfig,
+    ) -> bool {
+        config.detectors.ddos_guard
+            && driver.title().await.is_ok_and(|title| {
+                config.challenge_for_title(&title) == Some(ChallengeKind::DdosGuard)
+            })
     }
 
     /// Waits for the DDoS-Guard challenge to be solved, or times out.
-    pub async fn handle_challenge(driver: &mut thirtyfour::WebDriver, timeout: u64) -> Result<()> {
+    /// Bails out early if the request is aborted.
+    pub async fn handle_challenge(
+        driver: &mut thirtyfour::WebDriver,
+        timeout: u64,
+        config: &ChallengeConfig,
+        control: &RequestControl,
+    ) -> Result<()> {
         let start_time = std::time::Instant::now();
-        while is_protected(driver).await {
+        while is_protected(driver, config).await {
+            control.check()?;
             if start_time.elapsed().as_secs() > timeout {
-                return Err(anyhow::anyhow!("DDoS Guard challenge timed out"));
+                return Err(
+                    SolveError::Timeout("DDoS Guard challenge timed out".to_string()).into(),
+                );
             }
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            tokio::time::sleep(config.poll_interval()).await;
         }
 
         Ok(())
@@ -28,45 +71,404 @@ pub mod ddos_guard {
 pub mod cloudflare {
 
     use anyhow::Result;
+    use log::{debug, warn};
+    use std::collections::HashMap;
     use thirtyfour::prelude::*;
 
+    use super::{ChallengeKind, document_id};
+    use crate::browser::SolveError;
+    use crate::config::{ChallengeConfig, ScrappeyConfig};
+    use crate::inflight::RequestControl;
     use crate::scrappey::{ScrappeyClient, ScrappeyGetRequest, ScrappeyResponse};
 
+    /// Default title fragment of the Cloudflare challenge page.
+    pub const CHALLENGE_TITLE: &str = "Just a moment...";
+
     /// Returns true if the current page is protected by a Cloudflare challenge.
-    pub async fn is_protected(driver: &mut WebDriver) -> bool {
-        driver
-            .title()
-            .await
-            .is_ok_and(|title| title.contains("Just a moment..."))
+    /// Always false if Cloudflare detection is disabled, without reading the title.
+    pub async fn is_protected(driver: &mut WebDriver, config: &ChallengeConfig) -> bool {
+        config.detectors.cloudflare
+            && driver.title().await.is_ok_and(|title| {
+                config.challenge_for_title(&title) == Some(ChallengeKind::Cloudflare)
+            })
     }
 
     /// Waits for the Cloudflare challenge to be solved, or times out.
-    pub async fn handle_challenge(driver: &mut WebDriver, timeout: u64) -> Result<()> {
+    /// Bails out early if the request is aborted, or if the challenge keeps coming back: a managed
+    /// challenge that rejects the browser's fingerprint reloads into a new challenge instead of
+    /// the page, and won't ever clear locally.
+    pub async fn handle_challenge(
+        driver: &mut WebDriver,
+        timeout: u64,
+        config: &ChallengeConfig,
+        control: &RequestControl,
+    ) -> Result<()> {
         let start_time = std::time::Instant::now();
-        while is_protected(driver).await {
+        let mut document = document_id(driver).await;
+        let mut reloads = 0;
+        while is_protected(driver, config).await {
+            control.check()?;
             if start_time.elapsed().as_secs() > timeout {
-                return Err(anyhow::anyhow!("Cloudflare challenge timed out"));
+                return Err(
+                    SolveError::Timeout("Cloudflare challenge timed out".to_string()).into(),
+                );
+            }
+
+            let current = document_id(driver).await;
+            let reloaded = current.is_some() && document.is_some() && current != document;
+            document = current.or(document);
+            if reloaded {
+                reloads += 1;
+                debug!("Cloudflare challenge came back after a reload ({reloads} times)");
+                if config.max_reloads > 0 && reloads >= config.max_reloads {
+                    warn!("Cloudflare challenge loop detected: re-presented {reloads} times");
+                    return Err(SolveError::ChallengeLoop(format!(
+                        "Cloudflare challenge loop: re-presented {reloads} times after reloading"
+                    ))
+                    .into());
+                }
             }
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            tokio::time::sleep(config.poll_interval()).await;
         }
 
         Ok(())
     }
 
     /// Fallback: Use Scrappey API to resolve Cloudflare challenge if browser automation fails.
+    /// `local_storage` is seeded into the page's origin and `headers` are sent with the request,
+    /// if not empty.
     pub async fn scrappey_resolve(
         url: String,
-        api_key: String,
+        scrappey: &ScrappeyConfig,
         proxy: &str,
+        local_storage: &HashMap<String, String>,
+        headers: &HashMap<String, String>,
         timeout: u64,
     ) -> Result<ScrappeyResponse> {
         // If we reach here, the challenge was not solved in time, we need to use a third-party service
-        let client = ScrappeyClient::new(api_key);
+        let client = ScrappeyClient::from_config(scrappey)?;
         let request = ScrappeyGetRequest {
             url,
             proxy: Some(proxy.to_string()),
+            local_storage: (!local_storage.is_empty()).then(|| local_storage.clone()),
+            custom_headers: (!headers.is_empty()).then(|| headers.clone()),
             ..Default::default()
         };
         client.get(request, timeout).await
     }
 }
+
+/// Cloudflare Turnstile widgets embedded in otherwise normal pages.
+/// Managed widgets usually solve themselves, but only in a tab that looks focused and visible,
+/// and they take a few seconds to do so.
+pub mod turnstile {
+    use anyhow::Result;
+    use log::debug;
+    use thirtyfour::extensions::cdp::ChromeDevTools;
+    use thirtyfour::prelude::*;
+
+    use crate::browser::SolveError;
+    use crate::config::ChallengeConfig;
+    use crate::inflight::RequestControl;
+
+    /// Value of the page's Turnstile response input: `None` if there is no widget, empty until
+    /// the widget is solved.
+    async fn response(driver: &WebDriver) -> Option<String> {
+        driver
+            .execute(
+                "const input = document.querySelector('input[name=\"cf-turnstile-response\"]'); \
+                 return input ? input.value : null;",
+                vec![],
+            )
+            .await
+            .ok()?
+            .convert::<Option<String>>()
+            .ok()
+            .flatten()
+    }
+
+    /// Returns true if the current page has an unsolved Turnstile widget.
+    /// Always false if Turnstile detection is disabled, without running a script.
+    pub async fn is_protected(driver: &WebDriver, config: &ChallengeConfig) -> bool {
+        config.detectors.turnstile && response(driver).await.is_some_and(|r| r.is_empty())
+    }
+
+    /// Make the tab look focused and visible, as a headless or background tab otherwise doesn't
+    /// get the widget to run. Failures are only logged, as the widget may solve anyway.
+    async fn simulate_focus(driver: &WebDriver) {
+        let dev_tools = ChromeDevTools::new(driver.handle.clone());
+        let result = async {
+            dev_tools.execute_cdp("Page.bringToFront").await?;
+            dev_tools
+                .execute_cdp_with_params(
+                    "Emulation.setFocusEmulationEnabled",
+                    serde_json::json!({ "enabled": true }),
+                )
+                .await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            debug!("Failed to simulate focus for Turnstile: {e}");
+        }
+    }
+
+    /// Waits for the Turnstile widget to fill in its response, for up to the configured wait
+    /// budget (`turnstile_wait_secs`) or `timeout`, whichever is shorter.
+    /// Bails out early if the request is aborted.
+    pub async fn handle_challenge(
+        driver: &WebDriver,
+        timeout: u64,
+        config: &ChallengeConfig,
+        control: &RequestControl,
+    ) -> Result<()> {
+        let timeout = timeout.min(config.turnstile_wait_secs);
+        let start_time = std::time::Instant::now();
+        simulate_focus(driver).await;
+        while is_protected(driver, config).await {
+            control.check()?;
+            if start_time.elapsed().as_secs() > timeout {
+                return Err(SolveError::Timeout(format!(
+                    "Turnstile was not solved within {timeout} seconds"
+                ))
+                .into());
+            }
+            tokio::time::sleep(config.poll_interval()).await;
+            // Scripts on the page may have blurred the tab meanwhile
+            simulate_focus(driver).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Detection and handling of challenges embedded in iframes, which carry their own challenge
+/// that the top-level title checks don't see.
+pub mod iframe {
+    use anyhow::Result;
+    use log::info;
+    use thirtyfour::prelude::*;
+
+    use super::ChallengeKind;
+    use crate::browser::SolveError;
+    use crate::config::ChallengeConfig;
+    use crate::inflight::RequestControl;
+
+    /// Kind of challenge shown in the current frame, if any.
+    /// WebDriver's title command always reads the top-level document, so the frame's own title
+    /// is read with a script instead.
+    async fn frame_challenge(
+        driver: &WebDriver,
+        config: &ChallengeConfig,
+    ) -> Option<ChallengeKind> {
+        let title = driver
+            .execute("return document.title;", vec![])
+            .await
+            .ok()?
+            .convert::<String>()
+            .ok()?;
+        config
+            .challenge_for_title(&title)
+            .filter(|kind| config.detectors.is_enabled(*kind))
+    }
+
+    /// Check each of the page's iframes (not nested ones) for a challenge and wait for it to be
+    /// solved in place, or time out. Returns the kind of the first challenge found.
+    /// Challenges whose detector is disabled are ignored.
+    /// The driver is always switched back to the top-level document afterwards.
+    pub async fn handle_challenges(
+        driver: &WebDriver,
+        timeout: u64,
+        config: &ChallengeConfig,
+        control: &RequestControl,
+    ) -> Result<Option<ChallengeKind>> {
+        let start_time = std::time::Instant::now();
+        let frames = driver.find_all(By::Tag("iframe")).await?;
+        let mut found = None;
+
+        for (index, frame) in frames.into_iter().enumerate() {
+            let result = async {
+                frame.enter_frame().await?;
+                let Some(kind) = frame_challenge(driver, config).await else {
+                    return Ok(None);
+                };
+                info!("{kind:?} challenge detected in iframe {index}, handling...");
+                while frame_challenge(driver, config).await.is_some() {
+                    control.check()?;
+                    if start_time.elapsed().as_secs() > timeout {
+                        return Err(anyhow::Error::new(SolveError::Timeout(format!(
+                            "{kind:?} challenge in iframe timed out"
+                        ))));
+                    }
+                    tokio::time::sleep(config.poll_interval()).await;
+                }
+                info!("{kind:?} challenge in iframe {index} handled successfully.");
+                Ok(Some(kind))
+            }
+            .await;
+
+            // Restore the top-level context before doing anything else, even on failure
+            driver.enter_default_frame().await?;
+            found = found.or(result?);
+        }
+
+        Ok(found)
+    }
+}
+
+/// `<meta http-equiv="refresh">` redirects, which some interstitials use instead of scripts.
+/// They don't change the page title, so the challenge checks never notice them.
+pub mod meta_refresh {
+    use anyhow::Result;
+    use log::{debug, info};
+    use std::time::{Duration, Instant};
+    use thirtyfour::prelude::*;
+
+    use super::document_id;
+    use crate::browser::SolveError;
+    use crate::config::ChallengeConfig;
+    use crate::inflight::RequestControl;
+
+    /// Longest refresh delay that is followed. Longer ones are usually pages that periodically
+    /// reload themselves (e.g. news sites), not interstitials.
+    const MAX_DELAY: Duration = Duration::from_secs(10);
+
+    /// Extra time the browser is given to perform the refresh itself before it is followed
+    /// manually.
+    const REFRESH_GRACE: Duration = Duration::from_secs(2);
+
+    /// A refresh declared by the current page.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct MetaRefresh {
+        pub delay: Duration,
+        /// Absolute URL the refresh goes to (the page itself if the tag names none).
+        pub target: String,
+    }
+
+    /// The refresh declared by the current page, if any and if its delay is short enough to be
+    /// an interstitial's.
+    pub async fn detect(driver: &WebDriver) -> Option<MetaRefresh> {
+        let (content, location) = driver
+            .execute(
+                "const meta = document.querySelector('meta[http-equiv=\"refresh\" i]'); \
+                 return meta ? [meta.content, location.href] : null;",
+                vec![],
+            )
+            .await
+            .ok()?
+            .convert::<Option<(String, String)>>()
+            .ok()??;
+        let refresh = parse(&content, &location)?;
+        if refresh.delay > MAX_DELAY {
+            debug!(
+                "Ignoring meta refresh after {}s to {}",
+                refresh.delay.as_secs(),
+                refresh.target
+            );
+            return None;
+        }
+        Some(refresh)
+    }
+
+    /// Parse a refresh tag's `content`, such as `5; url=/next` or `0;URL='https://example.com/'`,
+    /// resolving its URL against the page's `location`.
+    /// Delays that are negative or too large for a `Duration` (e.g. `inf`) make the tag ignored.
+    fn parse(content: &str, location: &str) -> Option<MetaRefresh> {
+        let (delay, rest) = content
+            .split_once([';', ','])
+            .map_or((content, ""), |(delay, rest)| (delay, rest.trim()));
+        let delay = delay.trim().parse::<f64>().ok()?;
+        let delay = Duration::try_from_secs_f64(delay).ok()?;
+        let target = match rest.get(..3) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("url") => rest[3..]
+                .trim_start()
+                .strip_prefix('=')
+                .unwrap_or(&rest[3..])
+                .trim()
+                .trim_matches(['\'', '"']),
+            _ => rest.trim_matches(['\'', '"']),
+        };
+        let base = url::Url::parse(location).ok()?;
+        let target = if target.is_empty() {
+            base
+        } else {
+            base.join(target).ok()?
+        };
+        Some(MetaRefresh {
+            delay,
+            target: target.to_string(),
+        })
+    }
+
+    /// Wait for the browser to perform the refresh, and navigate to its target manually if it
+    /// hasn't within the refresh delay (plus a grace period), or times out.
+    pub async fn follow(
+        driver: &WebDriver,
+        refresh: &MetaRefresh,
+        timeout: u64,
+        config: &ChallengeConfig,
+        control: &RequestControl,
+    ) -> Result<()> {
+        info!(
+            "Meta refresh to {} after {}s detected, following it",
+            refresh.target,
+            refresh.delay.as_secs()
+        );
+        let start_time = Instant::now();
+        let wait = (refresh.delay + REFRESH_GRACE).min(Duration::from_secs(timeout));
+        let document = document_id(driver).await;
+        while start_time.elapsed() < wait {
+            control.check()?;
+            tokio::time::sleep(config.poll_interval()).await;
+            let current = document_id(driver).await;
+            if current.is_some() && current != document {
+                debug!("The browser followed the meta refresh itself");
+                return Ok(());
+            }
+        }
+
+        if start_time.elapsed().as_secs() >= timeout {
+            return Err(
+                SolveError::Timeout("Timed out following the meta refresh".to_string()).into(),
+            );
+        }
+        control.check()?;
+        driver.goto(&refresh.target).await?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const LOCATION: &str = "https://example.com/page";
+
+        #[test]
+        fn parses_delay_and_relative_url() {
+            let refresh = parse("5; url=/x", LOCATION).unwrap();
+            assert_eq!(refresh.delay, Duration::from_secs(5));
+            assert_eq!(refresh.target, "https://example.com/x");
+        }
+
+        #[test]
+        fn parses_quoted_absolute_url() {
+            let refresh = parse("0;URL='https://other.example/next'", LOCATION).unwrap();
+            assert_eq!(refresh.delay, Duration::ZERO);
+            assert_eq!(refresh.target, "https://other.example/next");
+        }
+
+        #[test]
+        fn refresh_without_url_targets_the_page() {
+            let refresh = parse("1.5", LOCATION).unwrap();
+            assert_eq!(refresh.delay, Duration::from_millis(1500));
+            assert_eq!(refresh.target, LOCATION);
+        }
+
+        #[test]
+        fn unrepresentable_delays_are_ignored() {
+            for content in ["inf", "1e20", "1e400", "NaN", "-1; url=/x", "soon; url=/x"] {
+                assert_eq!(parse(content, LOCATION), None, "{content}");
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Operator-supplied cookies loaded from a mounted file.
+//! Lets long-lived cookies (e.g. a paid account's session) be pre-seeded into the browser without
+//! every client having to send them. The file is a JSON array of FlareSolverr-style cookies.
+
+use anyhow::Result;
+use log::warn;
+use thirtyfour::Cookie;
+
+use crate::cookies;
+use crate::flaresolverr::FlaresolverrCookie;
+
+/// Read the cookie file. Cookies without a domain are skipped, since there would be no way to
+/// tell which sites they belong to.
+pub fn load(path: &str) -> Result<Vec<Cookie>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read cookies file '{path}': {e}"))?;
+    let cookies: Vec<FlaresolverrCookie> = serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("Invalid cookies file '{path}': {e}"))?;
+
+    Ok(cookies
+        .into_iter()
+        .filter(|cookie| {
+            let has_domain = cookie.domain.as_deref().is_some_and(|d| !d.is_empty());
+            if !has_domain {
+                warn!("Skipping cookie '{}' from {path}: no domain", cookie.name);
+            }
+            has_domain
+        })
+        .map(to_cookie)
+        .collect())
+}
+
+/// Unexpired cookies from the file that apply to the given URL's host, including those set on a
+/// parent domain.
+pub fn for_url(cookies: Vec<Cookie>, url: &str) -> Vec<Cookie> {
+    let Some(host) = url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+    else {
+        return Vec::new();
+    };
+    let now = chrono::Utc::now().timestamp();
+
+    cookies
+        .into_iter()
+        .filter(|cookie| cookie.expiry.is_none_or(|expiry| expiry > now))
+        .filter(|cookie| {
+            let domain = cookie
+                .domain
+                .as_deref()
+                .unwrap_or_default()
+                .trim_start_matches('.')
+                .to_ascii_lowercase();
+            host == domain || host.ends_with(&format!(".{domain}"))
+        })
+        .collect()
+}
+
+/// Convert a FlareSolverr cookie (expiry in seconds, `-1` for session cookies) to a browser cookie.
+fn to_cookie(cookie: FlaresolverrCookie) -> Cookie {
+    Cookie {
+        name: cookie.name,
+        value: cookie.value,
+        path: cookie.path,
+        domain: cookie.domain,
+        secure: cookie.secure,
+        expiry: cookies::expiry_from_secs(cookie.expires),
+        same_site: cookie
+            .same_site
+            .as_deref()
+            .and_then(cookies::parse_same_site),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_cookie(expires: f64) -> FlaresolverrCookie {
+        FlaresolverrCookie {
+            name: "session".to_string(),
+            value: "abc".to_string(),
+            domain: Some(".example.com".to_string()),
+            path: Some("/".to_string()),
+            expires,
+            http_only: true,
+            secure: Some(true),
+            same_site: Some("Lax".to_string()),
+        }
+    }
+
+    #[test]
+    fn expiry_round_trips_through_the_file_format() {
+        let cookie = to_cookie(file_cookie(1_700_000_000.0));
+        assert_eq!(FlaresolverrCookie::from(cookie).expires, 1_700_000_000.0);
+        let session = to_cookie(file_cookie(-1.0));
+        assert_eq!(FlaresolverrCookie::from(session).expires, -1.0);
+    }
+}
@@ -0,0 +1,49 @@
+//! Conversions shared by the cookie formats the solver reads. FlareSolverr's, Scrappey's and the
+//! DevTools protocol's all give the expiry in (possibly fractional) seconds and SameSite by name.
+
+use thirtyfour::SameSite;
+
+/// Expiry of a cookie from seconds since the epoch, truncated to whole seconds.
+/// Zero and negative values (usually `-1`) mark a session cookie, which has no expiry.
+pub fn expiry_from_secs(secs: f64) -> Option<i64> {
+    (secs > 0.0).then_some(secs as i64)
+}
+
+/// Parse a SameSite attribute (`Lax`, `Strict` or `None`, in any case). Other values are ignored.
+pub fn parse_same_site(value: &str) -> Option<SameSite> {
+    match value.to_ascii_lowercase().as_str() {
+        "lax" => Some(SameSite::Lax),
+        "strict" => Some(SameSite::Strict),
+        "none" => Some(SameSite::None),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_expiry() {
+        assert_eq!(expiry_from_secs(1_700_000_000.0), Some(1_700_000_000));
+    }
+
+    #[test]
+    fn fractional_expiry_is_truncated() {
+        assert_eq!(expiry_from_secs(1_700_000_000.75), Some(1_700_000_000));
+    }
+
+    #[test]
+    fn session_cookie_has_no_expiry() {
+        assert_eq!(expiry_from_secs(-1.0), None);
+        assert_eq!(expiry_from_secs(0.0), None);
+    }
+
+    #[test]
+    fn same_site_is_parsed_in_any_case() {
+        assert!(matches!(parse_same_site("Lax"), Some(SameSite::Lax)));
+        assert!(matches!(parse_same_site("STRICT"), Some(SameSite::Strict)));
+        assert!(matches!(parse_same_site("none"), Some(SameSite::None)));
+        assert!(parse_same_site("unspecified").is_none());
+    }
+}
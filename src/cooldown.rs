@@ -0,0 +1,71 @@
+//! Per-host request spacing.
+//! Hitting a protected site back-to-back is a quick way to get the proxy IP rate-limited or
+//! blocked, so requests to the same site can be spaced out by a minimum interval.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tracks when each site may next be requested.
+pub struct HostCooldown {
+    interval: Duration,
+    /// Earliest time the next request to each site may start.
+    next_allowed: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostCooldown {
+    /// Create a tracker enforcing `interval` between requests to the same site (zero disables it).
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_allowed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true if a cooldown is configured.
+    pub fn is_enabled(&self) -> bool {
+        !self.interval.is_zero()
+    }
+
+    /// Book the next slot for a request to the site, if it's within `max_wait`.
+    /// Returns how long to wait before sending the request, or, if the slot is too far away,
+    /// how long until it would be available (without booking it).
+    pub fn reserve(&self, site: &str, max_wait: Duration) -> Result<Duration, Duration> {
+        let now = Instant::now();
+        let mut next_allowed = self.next_allowed.lock().unwrap();
+
+        // Forget sites that haven't been requested within the interval
+        next_allowed.retain(|_, at| *at > now);
+
+        let start = next_allowed.get(site).copied().unwrap_or(now).max(now);
+        let wait = start - now;
+        if wait > max_wait {
+            return Err(wait);
+        }
+        next_allowed.insert(site.to_string(), start + self.interval);
+        Ok(wait)
+    }
+}
+
+/// Best-effort registrable domain of a URL's host (e.g. `www.example.co.uk` -> `example.co.uk`),
+/// so that subdomains of the same site share a cooldown. IP addresses are used as-is.
+pub fn site_of(url: &str) -> Option<String> {
+    let url = url::Url::parse(url).ok()?;
+    let host = match url.host()? {
+        url::Host::Domain(domain) => domain.trim_end_matches('.').to_ascii_lowercase(),
+        ip => return Some(ip.to_string()),
+    };
+
+    let labels: Vec<&str> = host.split('.').collect();
+    // Country-code TLDs commonly register under a second level, e.g. `co.uk`, `com.au`
+    let keep = match labels.as_slice() {
+        [.., second, tld]
+            if tld.len() == 2
+                && matches!(*second, "co" | "com" | "net" | "org" | "gov" | "edu" | "ac") =>
+        {
+            3
+        }
+        _ => 2,
+    };
+    Some(labels[labels.len().saturating_sub(keep)..].join("."))
+}
@@ -0,0 +1,231 @@
+//! Deferred saving of the persisted browser data (`persistent.json`).
+//! Writing the file after every request is a lot of disk I/O under load, so with a save interval
+//! the latest data is kept in memory, read back by the following requests, and written at most
+//! once per interval and on shutdown.
+//!
+//! Requests sharing the data synchronize on a read-write lock: any number of requests can load
+//! the data at once, while saving it waits for them and excludes everything else, so that no
+//! request reads a half-written file. The lock is only held while loading or saving, not for the
+//! whole solve, so each save first merges in the cookies other requests saved meanwhile.
+
+use anyhow::Result;
+use log::{debug, warn};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::browser::BrowserData;
+
+/// Browser data waiting to be written to disk.
+pub struct DataStore {
+    path: String,
+    interval: Duration,
+    pending: Mutex<Option<BrowserData>>,
+    lock: RwLock<()>,
+    lock_timeout: Duration,
+}
+
+impl DataStore {
+    /// Create a store for the file at `path`, written at most every `interval` (zero writes on
+    /// every save). Requests wait at most `lock_timeout` for the data lock.
+    pub fn new(path: String, interval: Duration, lock_timeout: Duration) -> Self {
+        Self {
+            path,
+            interval,
+            pending: Mutex::new(None),
+            lock: RwLock::new(()),
+            lock_timeout,
+        }
+    }
+
+    /// Lock the data for loading; other loads can proceed at the same time.
+    /// Returns None if a save held the lock for longer than the lock timeout.
+    pub async fn read(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        tokio::time::timeout(self.lock_timeout, self.lock.read())
+            .await
+            .ok()
+    }
+
+    /// Lock the data for saving, waiting for loads in progress and excluding everything else.
+    /// Returns None if the lock couldn't be taken within the lock timeout.
+    pub async fn write(&self) -> Option<RwLockWriteGuard<'_, ()>> {
+        tokio::time::timeout(self.lock_timeout, self.lock.write())
+            .await
+            .ok()
+    }
+
+    /// Returns true if saves are held in memory rather than written right away.
+    pub fn is_deferred(&self) -> bool {
+        !self.interval.is_zero()
+    }
+
+    /// Data saved since the last write, which is newer than the file's.
+    pub fn pending(&self) -> Option<BrowserData> {
+        self.pending.lock().unwrap().clone()
+    }
+
+    /// Hold data until the next flush. It replaces any data pending from earlier requests.
+    pub fn defer(&self, data: BrowserData) {
+        *self.pending.lock().unwrap() = Some(data);
+    }
+
+    /// Write the pending data, if any, to disk, off the async runtime's worker threads.
+    /// The data stays pending until it is written, so that requests never read a stale file.
+    /// Call it with the data lock held for writing, so that no save replaces the data meanwhile.
+    pub async fn flush(&self) -> Result<()> {
+        let Some(data) = self.pending() else {
+            return Ok(());
+        };
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || write_data(&path, &data)).await??;
+        *self.pending.lock().unwrap() = None;
+        debug!("Saved browser data to {}", self.path);
+        Ok(())
+    }
+
+    /// Flush the pending data once per interval, until the process exits.
+    pub async fn run_flusher(&self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.tick().await; // The first tick completes immediately, with nothing to save yet
+        loop {
+            interval.tick().await;
+            // Like any other save, flushing must not overlap with requests loading the data
+            let Some(_lock) = self.write().await else {
+                warn!("Timed out waiting for the browser data lock, saving at the next interval");
+                continue;
+            };
+            if let Err(e) = self.flush().await {
+                warn!("Failed to save browser data: {e}");
+            }
+        }
+    }
+}
+
+/// Write browser data to `path` through a temporary file that replaces it once complete, so that
+/// a crash mid-write leaves the previous file intact.
+pub fn write_data(path: &str, data: &BrowserData) -> Result<()> {
+    let temp_path = format!("{path}.tmp");
+    let write = || -> Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&temp_path)?);
+        serde_json::to_writer_pretty(&mut file, data)?;
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    };
+    write().map_err(|e| anyhow::anyhow!("Failed to write browser data to {path}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::browser::Browser;
+    use std::sync::Arc;
+    use thirtyfour::Cookie;
+
+    fn store() -> DataStore {
+        DataStore::new(String::new(), Duration::ZERO, Duration::from_millis(50))
+    }
+
+    #[tokio::test]
+    async fn loads_proceed_together_and_exclude_saves() {
+        let store = store();
+        let first = store.read().await.expect("first load");
+        let second = store.read().await.expect("concurrent load");
+        assert!(store.write().await.is_none(), "save while loading");
+        drop((first, second));
+        assert!(store.write().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn concurrent_loads_do_not_wait_for_each_other() {
+        let store = Arc::new(store());
+        let barrier = Arc::new(tokio::sync::Barrier::new(2));
+        let loads: Vec<_> = (0..2)
+            .map(|_| {
+                let (store, barrier) = (Arc::clone(&store), Arc::clone(&barrier));
+                tokio::spawn(async move {
+                    let _lock = store.read().await.expect("load");
+                    // Only passes if the other task holds its read lock at the same time
+                    barrier.wait().await;
+                })
+            })
+            .collect();
+        for load in loads {
+            tokio::time::timeout(Duration::from_secs(1), load)
+                .await
+                .expect("loads blocked each other")
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_saves_keep_each_others_cookies() {
+        let store = DataStore::new(
+            String::new(),
+            Duration::from_secs(60),
+            Duration::from_millis(50),
+        );
+        let mut first = Browser::new();
+        let mut second = Browser::new();
+        first.data.cookies.push(Cookie::new("first", "1"));
+        second.data.cookies.push(Cookie::new("second", "2"));
+
+        for browser in [&mut first, &mut second] {
+            let _lock = store.write().await.unwrap();
+            browser.merge_saved_data(&store, "");
+            browser.defer_save(&store);
+        }
+
+        let mut names: Vec<_> = store
+            .pending()
+            .unwrap()
+            .cookies
+            .into_iter()
+            .map(|cookie| cookie.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn saves_exclude_loads_and_other_saves() {
+        let store = store();
+        let save = store.write().await.expect("save");
+        assert!(store.read().await.is_none(), "load while saving");
+        assert!(store.write().await.is_none(), "save while saving");
+        drop(save);
+        assert!(store.read().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn flush_replaces_the_file_and_clears_the_pending_data() {
+        let path = std::env::temp_dir().join(format!("persistent-{}.json", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(&path, "previous").unwrap();
+        let store = DataStore::new(path.clone(), Duration::from_secs(60), Duration::ZERO);
+
+        let mut browser = Browser::new();
+        browser.data.cookies.push(Cookie::new("saved", "1"));
+        browser.defer_save(&store);
+        store.flush().await.unwrap();
+
+        assert!(store.pending().is_none());
+        let saved: BrowserData =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.cookies[0].name, "saved");
+        assert!(!std::path::Path::new(&format!("{path}.tmp")).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_flush_keeps_the_data_pending() {
+        let store = DataStore::new(
+            "/nonexistent/dir/persistent.json".to_string(),
+            Duration::from_secs(60),
+            Duration::ZERO,
+        );
+        Browser::new().defer_save(&store);
+        assert!(store.flush().await.is_err());
+        assert!(store.pending().is_some());
+    }
+}
@@ -0,0 +1,177 @@
+//! Browser control operations that plain WebDriver doesn't cover (cookies with all their
+//! attributes, the user agent, the document's HTTP status), behind a common trait so that they
+//! don't depend on one protocol. Only Chrome DevTools Protocol is implemented for now; WebDriver
+//! BiDi, which Firefox also speaks, can be added as another implementation once thirtyfour
+//! supports it.
+//! Chrome-only features such as emulation overrides and PDF rendering still use CDP directly.
+
+use anyhow::Result;
+use log::warn;
+use std::sync::Arc;
+use thirtyfour::extensions::cdp::ChromeDevTools;
+use thirtyfour::session::handle::SessionHandle;
+use thirtyfour::{Cookie, WebDriver};
+
+use crate::cookies;
+
+/// Operations on a browser session beyond what WebDriver offers.
+pub trait DriverControl {
+    /// Set cookies in the browser. Cookies the browser rejects are only logged.
+    async fn set_cookies(&self, cookies: &[Cookie]) -> Result<()>;
+
+    /// All cookies in the browser, for every domain (not just the current page's).
+    async fn get_cookies(&self) -> Result<Vec<Cookie>>;
+
+    /// Override the user agent for the following requests.
+    async fn set_user_agent(&self, user_agent: &str) -> Result<()>;
+
+    /// HTTP status of the current document, if the browser reports one.
+    async fn status(&self) -> Result<Option<u16>>;
+}
+
+/// Chrome DevTools Protocol implementation.
+pub struct Cdp {
+    handle: Arc<SessionHandle>,
+    dev_tools: ChromeDevTools,
+}
+
+impl Cdp {
+    pub fn new(driver: &WebDriver) -> Self {
+        Self {
+            handle: driver.handle.clone(),
+            dev_tools: ChromeDevTools::new(driver.handle.clone()),
+        }
+    }
+}
+
+impl DriverControl for Cdp {
+    async fn set_cookies(&self, cookies: &[Cookie]) -> Result<()> {
+        self.dev_tools.execute_cdp("Network.enable").await?;
+
+        for cookie in cookies {
+            let cookie_value = cdp_cookie_params(cookie)?;
+
+            // A rejected cookie shouldn't fail the request, but it usually means a failed solve
+            match self
+                .dev_tools
+                .execute_cdp_with_params("Network.setCookie", cookie_value)
+                .await
+            {
+                Ok(result) if result["success"] == false => warn!(
+                    "Browser rejected cookie '{}' for {}",
+                    cookie.name,
+                    cookie.domain.as_deref().unwrap_or("?")
+                ),
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "Failed to set cookie '{}' for {}: {e}",
+                    cookie.name,
+                    cookie.domain.as_deref().unwrap_or("?")
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_cookies(&self) -> Result<Vec<Cookie>> {
+        Ok(self
+            .dev_tools
+            .execute_cdp("Storage.getCookies")
+            .await?
+            .get("cookies")
+            .and_then(|c| c.as_array())
+            .map_or(Vec::new(), |arr| {
+                arr.iter().filter_map(from_cdp_cookie).collect()
+            }))
+    }
+
+    async fn set_user_agent(&self, user_agent: &str) -> Result<()> {
+        self.dev_tools
+            .execute_cdp_with_params(
+                "Network.setUserAgentOverride",
+                serde_json::json!({ "userAgent": user_agent }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Read from the Navigation Timing API, as CDP only reports statuses through events.
+    async fn status(&self) -> Result<Option<u16>> {
+        let status = self
+            .handle
+            .execute(
+                "const nav = performance.getEntriesByType('navigation')[0]; \
+                 return nav ? nav.responseStatus : 0;",
+                vec![],
+            )
+            .await?
+            .convert::<u16>()?;
+        Ok(Some(status).filter(|status| *status != 0))
+    }
+}
+
+/// Parameters of `Network.setCookie` for a cookie.
+/// CDP names the expiry `expires`, where WebDriver uses `expiry`; both are in seconds.
+fn cdp_cookie_params(cookie: &Cookie) -> Result<serde_json::Value> {
+    let mut params = serde_json::to_value(cookie)
+        .map_err(|e| anyhow::anyhow!("Failed to serialize cookie: {}", e))?;
+    if let Some(params) = params.as_object_mut()
+        && let Some(expiry) = params.remove("expiry")
+    {
+        params.insert("expires".to_string(), expiry);
+    }
+    Ok(params)
+}
+
+/// Convert a cookie from `Storage.getCookies`, whose `expires` is in (fractional) seconds and
+/// `-1` for session cookies.
+fn from_cdp_cookie(value: &serde_json::Value) -> Option<Cookie> {
+    let mut cookie: Cookie = serde_json::from_value(value.clone()).ok()?;
+    cookie.expiry = value["expires"]
+        .as_f64()
+        .and_then(cookies::expiry_from_secs);
+    Some(cookie)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookie_with_expiry(expiry: Option<i64>) -> Cookie {
+        let mut cookie = Cookie::new("cf_clearance", "token");
+        cookie.domain = Some(".example.com".to_string());
+        cookie.expiry = expiry;
+        cookie
+    }
+
+    fn cdp_cookie(expires: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "name": "cf_clearance",
+            "value": "token",
+            "domain": ".example.com",
+            "path": "/",
+            "expires": expires,
+        })
+    }
+
+    #[test]
+    fn cdp_params_name_the_expiry_expires() {
+        let params = cdp_cookie_params(&cookie_with_expiry(Some(1_700_000_000))).unwrap();
+        assert_eq!(params["expires"], 1_700_000_000);
+        assert!(params.get("expiry").is_none());
+    }
+
+    #[test]
+    fn cdp_params_leave_session_cookies_without_expiry() {
+        let params = cdp_cookie_params(&cookie_with_expiry(None)).unwrap();
+        assert!(params.get("expiry").is_none());
+        assert!(params.get("expires").is_none_or(serde_json::Value::is_null));
+    }
+
+    #[test]
+    fn cdp_cookie_expiry_is_read_from_expires() {
+        let cookie = from_cdp_cookie(&cdp_cookie(1_700_000_000.75.into())).unwrap();
+        assert_eq!(cookie.expiry, Some(1_700_000_000));
+    }
+}
@@ -0,0 +1,229 @@
+//! Registry of in-flight challenge-solving requests.
+//! Each request gets a cancellation token and a progress marker so that clients can abort
+//! a solve that they have given up on (saving browser time and Scrappey credits).
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// How far a request has progressed through the solving pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Stage {
+    Queued,
+    /// Trying Scrappey before the browser (`FALLBACK_ORDER=scrappey-first`).
+    Scrappey,
+    LaunchingBrowser,
+    Navigating,
+    SolvingChallenge,
+    ScrappeyFallback,
+    ExtractingResponse,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Stage::Queued => "queued",
+            Stage::Scrappey => "solving with Scrappey",
+            Stage::LaunchingBrowser => "launching browser",
+            Stage::Navigating => "navigating",
+            Stage::SolvingChallenge => "solving challenge",
+            Stage::ScrappeyFallback => "Scrappey fallback",
+            Stage::ExtractingResponse => "extracting response",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Cancellation and progress handle shared between a request and the registry.
+#[derive(Debug, Clone)]
+pub struct RequestControl {
+    token: CancellationToken,
+    stage: Arc<Mutex<Stage>>,
+}
+
+impl Default for RequestControl {
+    fn default() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            stage: Arc::new(Mutex::new(Stage::Queued)),
+        }
+    }
+}
+
+impl RequestControl {
+    /// Record that the request reached a new stage.
+    pub fn set_stage(&self, stage: Stage) {
+        *self.stage.lock().unwrap() = stage;
+    }
+
+    /// The stage the request is currently in.
+    pub fn stage(&self) -> Stage {
+        *self.stage.lock().unwrap()
+    }
+
+    /// Returns true once the request has been aborted.
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Wait until the request is aborted.
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Fail with an abort error if the request has been cancelled.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            return Err(anyhow::anyhow!(
+                "Request was aborted during {}",
+                self.stage()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Summary of an aborted request, returned by `request.abort`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AbortedRequest {
+    pub request_id: String,
+    pub session: Option<String>,
+    pub stage: Stage,
+    pub elapsed_ms: u64,
+}
+
+struct InFlight {
+    control: RequestControl,
+    session: Option<String>,
+    started_at: Instant,
+}
+
+/// Thread-safe registry of all active requests, keyed by request ID.
+#[derive(Default)]
+pub struct InFlightRequests {
+    requests: Mutex<HashMap<String, InFlight>>,
+}
+
+impl InFlightRequests {
+    /// Register a new request and return its control handle, or `None` if a request with the
+    /// same ID is already in flight (it would be unreachable by `request.abort` otherwise).
+    /// The request is removed from the registry when the returned guard is dropped.
+    pub fn register(
+        self: &Arc<Self>,
+        request_id: &str,
+        session: Option<String>,
+    ) -> Option<(RequestControl, InFlightGuard)> {
+        let control = RequestControl::default();
+        match self.requests.lock().unwrap().entry(request_id.to_string()) {
+            Entry::Occupied(_) => return None,
+            Entry::Vacant(entry) => {
+                entry.insert(InFlight {
+                    control: control.clone(),
+                    session,
+                    started_at: Instant::now(),
+                });
+            }
+        }
+        let guard = InFlightGuard {
+            registry: Arc::clone(self),
+            request_id: request_id.to_string(),
+        };
+        Some((control, guard))
+    }
+
+    /// Abort all in-flight requests matching the request ID or session.
+    pub fn abort(&self, request_id: Option<&str>, session: Option<&str>) -> Vec<AbortedRequest> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, inflight)| {
+                request_id.is_some_and(|rid| rid == id.as_str())
+                    || session.is_some_and(|s| inflight.session.as_deref() == Some(s))
+            })
+            .map(|(id, inflight)| {
+                inflight.control.token.cancel();
+                AbortedRequest {
+                    request_id: id.clone(),
+                    session: inflight.session.clone(),
+                    stage: inflight.control.stage(),
+                    elapsed_ms: inflight.started_at.elapsed().as_millis() as u64,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Removes a request from the registry when it completes.
+pub struct InFlightGuard {
+    registry: Arc<InFlightRequests>,
+    request_id: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.registry
+            .requests
+            .lock()
+            .unwrap()
+            .remove(&self.request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_request_id_is_rejected() {
+        let registry = Arc::new(InFlightRequests::default());
+        let (_control, guard) = registry.register("req-1", None).unwrap();
+        assert!(
+            registry
+                .register("req-1", Some("other".to_string()))
+                .is_none()
+        );
+
+        // The first request is still the one that gets aborted
+        let aborted = registry.abort(Some("req-1"), None);
+        assert_eq!(aborted.len(), 1);
+        assert_eq!(aborted[0].session, None);
+
+        // Once it completes, the ID can be reused
+        drop(guard);
+        assert!(registry.abort(Some("req-1"), None).is_empty());
+        assert!(registry.register("req-1", None).is_some());
+    }
+
+    #[test]
+    fn rejected_duplicate_does_not_unregister_the_original() {
+        let registry = Arc::new(InFlightRequests::default());
+        let (control, _guard) = registry.register("req-1", None).unwrap();
+        drop(registry.register("req-1", None));
+
+        registry.abort(Some("req-1"), None);
+        assert!(control.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn queued_request_can_be_aborted_while_waiting() {
+        let registry = Arc::new(InFlightRequests::default());
+        let (control, _guard) = registry.register("req-1", None).unwrap();
+        let wait = tokio::spawn({
+            let control = control.clone();
+            async move { control.cancelled().await }
+        });
+
+        let aborted = registry.abort(Some("req-1"), None);
+        assert_eq!(aborted[0].stage, Stage::Queued);
+        tokio::time::timeout(std::time::Duration::from_secs(1), wait)
+            .await
+            .expect("waiting request was not woken by the abort")
+            .unwrap();
+    }
+}
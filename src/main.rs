@@ -1,76 +1,350 @@
 use anyhow::Result;
-use log::{error, info};
+use log::{error, info, warn};
 use transparent::TransparentChild;
 
 // Module imports for browser automation, challenge handling, API server, proxy bridge, and Scrappey integration.
 mod browser;
+mod cache;
+mod cdp_log;
 mod challenge;
 mod config;
+mod cookie_file;
+mod cookies;
+mod cooldown;
+mod data_store;
+mod driver_control;
 mod flaresolverr;
 mod fwd_proxy;
+mod inflight;
+mod metrics;
+mod readiness;
 mod scrappey;
+mod screenshots;
+mod selftest;
+mod state_transfer;
+mod trackers;
+mod user_agent;
+mod webhook;
 use config::ServerConfig;
 use flaresolverr::FlareSolverrAPI;
+use metrics::Metrics;
+use readiness::Readiness;
+use std::sync::Arc;
 
-use crate::scrappey::ScrappeyClient;
+use crate::fwd_proxy::{FwdProxyConfig, HttpProxyBridge};
+use crate::scrappey::{InvalidApiKey, ScrappeyClient};
+use crate::webhook::{LowBalanceEvent, Webhook};
+
+/// Location of the chromedriver binary in the container image.
+const CHROMEDRIVER_PATH: &str = "/usr/bin/chromedriver";
+
+/// Dependencies that log a lot below `warn`. They stay quiet unless enabled by name in `RUST_LOG`.
+const NOISY_LOG_TARGETS: &[&str] = &[
+    "h2",
+    "hyper",
+    "hyper_util",
+    "reqwest",
+    "rustls",
+    "thirtyfour",
+    "tower_http",
+    "tracing::span",
+];
+
+/// How many times binding the proxy bridge is attempted before giving up.
+const BRIDGE_BIND_ATTEMPTS: u32 = 3;
 
 /// Entrypoint for the FlareSolverr-compatible server.
 /// Initializes logging, loads config, starts proxy bridge, launches chromedriver, and runs the API server.
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize env_logger for logging support
-    env_logger::init();
+    init_logging();
 
     // Load configuration from environment variables
     let config = config::load_from_env()?;
 
+    let metrics = Arc::new(Metrics::default());
+
     // Print scrappey API balance
-    info!("Checking Scrappey API balance...");
-    let scrappey_client = ScrappeyClient::new(config.scrappey.api_key.clone());
-    match scrappey_client.get_balance(30).await {
-        Ok(balance) => info!("Scrappey API balance: {}", balance.balance),
-        Err(e) => error!("Failed to get Scrappey API balance: {e}"),
+    let mut low_balance = LowBalanceAlert::new(
+        config.scrappey.low_balance_threshold,
+        config.webhook_url.clone().map(Webhook::new),
+    );
+    let scrappey_client = ScrappeyClient::from_config(&config.scrappey)?;
+    if config.scrappey.is_configured() {
+        info!("Checking Scrappey API balance...");
+        match scrappey_client.get_balance(30).await {
+            Ok(balance) => {
+                info!("Scrappey API balance: {}", balance.balance);
+                metrics.record_balance(balance.balance, chrono::Utc::now());
+                low_balance.check(balance.balance);
+            }
+            Err(e) if e.is::<InvalidApiKey>() => {
+                if config.scrappey.require_valid_key {
+                    return Err(e.context(
+                        "SCRAPPEY_API_KEY is invalid and REQUIRE_VALID_SCRAPPEY_KEY is set",
+                    ));
+                }
+                error!(
+                    "Your Scrappey API key appears to be invalid ({e}). Every Scrappey fallback \
+                     will fail until SCRAPPEY_API_KEY is fixed; unset it to run with the browser only."
+                );
+            }
+            Err(e) => error!("Failed to get Scrappey API balance: {e}"),
+        }
+    }
+
+    // Keep an eye on credit usage in the background
+    if config.scrappey.is_configured() && config.scrappey.balance_interval_mins > 0 {
+        tokio::spawn(monitor_scrappey_balance(
+            scrappey_client,
+            Arc::clone(&metrics),
+            config.scrappey.balance_interval_mins,
+            low_balance,
+        ));
     }
 
+    // Keep failure screenshots from piling up
+    tokio::spawn(screenshots::run_cleanup(config.screenshots.clone()));
+
+    // Subsystems report here once they're up, for the /ready endpoint
+    let readiness = Arc::new(Readiness::default());
+
     // Start the local proxy bridge in the background
-    start_proxy_bridge(&config).await?;
+    let bridge = start_proxy_bridge(&config, &readiness, &metrics).await?;
 
-    // Start the chromedriver process (for browser automation)
-    let mut chromedriver = start_chromedriver()?;
+    // Start the chromedriver process (for browser automation) and wait for it in the background,
+    // then warm up the browser if configured
+    let mut chromedriver = start_chromedriver().inspect_err(|e| error!("{e}")).ok();
+    match (&chromedriver, config.scrappey.is_configured()) {
+        (None, false) => error!(
+            "No solving engine available: chromedriver could not be started and SCRAPPEY_API_KEY \
+             is not set, so every request will fail"
+        ),
+        (Some(_), false) => warn!(
+            "SCRAPPEY_API_KEY is not set: challenges the browser can't solve on its own will fail"
+        ),
+        _ => {}
+    }
+    tokio::spawn({
+        let config = config.clone();
+        let readiness = Arc::clone(&readiness);
+        async move {
+            readiness::wait_for_chromedriver(config.webdriver.url.clone(), readiness).await;
+            if let Some(url) = &config.warmup_url {
+                warm_up_browser(&config, url).await;
+            }
+        }
+    });
 
     // Run the Axum API server and handle graceful shutdown
-    run_server(config, &mut chromedriver).await?;
+    run_server(
+        config,
+        readiness,
+        metrics,
+        bridge,
+        chromedriver.as_deref_mut(),
+    )
+    .await?;
 
     Ok(())
 }
 
 /// Start the proxy bridge in a background task
-/// Start the HTTP-to-HTTP proxy bridge in a background task.
-/// This bridge allows the browser to use a local proxy that forwards to an upstream proxy (with optional auth).
-async fn start_proxy_bridge(config: &ServerConfig) -> Result<()> {
-    use crate::fwd_proxy::{FwdProxyConfig, HttpProxyBridge};
-
-    // Convert our config to the fwd_proxy module's config
-    let proxy_config = if config.proxy.username.is_some() && config.proxy.password.is_some() {
-        FwdProxyConfig::with_auth(
-            config.proxy.host.clone(),
-            config.proxy.port,
-            config.proxy.username.as_ref().unwrap().clone(),
-            config.proxy.password.as_ref().unwrap().clone(),
-        )
-    } else {
-        FwdProxyConfig::new(config.proxy.host.clone(), config.proxy.port)
-    };
+/// Start the HTTP-to-HTTP proxy bridges in background tasks, one per upstream proxy.
+/// A bridge allows the browser to use a local proxy that forwards to an upstream proxy (with optional auth).
+/// Returns the primary proxy's bridge, which also routes requests' own proxies, unless the
+/// primary proxy is used without one.
+async fn start_proxy_bridge(
+    config: &ServerConfig,
+    readiness: &Readiness,
+    metrics: &Arc<Metrics>,
+) -> Result<Option<HttpProxyBridge>> {
+    let mut primary = None;
+    for (index, proxy) in config.proxies().into_iter().enumerate() {
+        // The browser connects to SOCKS5 proxies directly
+        if proxy.is_direct() {
+            info!(
+                "Using SOCKS5 proxy {}:{} directly, without a bridge",
+                proxy.host, proxy.port
+            );
+            continue;
+        }
 
-    // Bind and spawn the proxy bridge server
-    let mut bridge = HttpProxyBridge::new(proxy_config);
-    bridge.bind("0.0.0.0:8080".parse()?).await?;
-    tokio::spawn(async move {
-        if let Err(e) = bridge.serve().await {
-            error!("Error running proxy bridge: {e}");
+        // Convert our config to the fwd_proxy module's config
+        let proxy_config =
+            if let (Some(username), Some(password)) = (&proxy.username, &proxy.password) {
+                FwdProxyConfig::with_auth(
+                    proxy.host.clone(),
+                    proxy.port,
+                    username.clone(),
+                    password.clone(),
+                )
+            } else {
+                FwdProxyConfig::new(proxy.host.clone(), proxy.port)
+            }
+            .with_pool(
+                config.bridge.pool_max_idle,
+                std::time::Duration::from_secs(config.bridge.pool_idle_timeout_secs),
+            )
+            .with_always_connect(config.bridge.always_connect)
+            .with_reset_retries(config.bridge.reset_retries)
+            .with_max_tunnels_per_client(config.bridge.max_tunnels_per_client);
+
+        // Bind and spawn the proxy bridge server, retrying briefly in case the port is still being
+        // released (e.g. right after a container restart)
+        let port = config.bridge.port + index as u16;
+        let addr = std::net::SocketAddr::new(config.bridge.bind_address, port);
+        let mut bridge = HttpProxyBridge::new(proxy_config).with_metrics(Arc::clone(metrics));
+        let mut attempt = 1;
+        while let Err(e) = bridge.bind(addr).await {
+            if attempt >= BRIDGE_BIND_ATTEMPTS {
+                return Err(anyhow::anyhow!(
+                    "Failed to bind the proxy bridge to {addr}: {e}. \
+                     Is another process using port {port}? Set BRIDGE_PORT to use a different port."
+                ));
+            }
+            warn!(
+                "Failed to bind the proxy bridge to {addr} (attempt {attempt}): {e}, retrying..."
+            );
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            attempt += 1;
         }
-    });
-    Ok(())
+        if index > 0 {
+            info!(
+                "Proxy bridge on port {port} forwards to pool proxy {}:{}",
+                proxy.host, proxy.port
+            );
+        } else {
+            primary = Some(bridge.clone());
+        }
+        tokio::spawn(async move {
+            if let Err(e) = bridge.serve().await {
+                error!("Error running proxy bridge: {e}");
+            }
+        });
+    }
+    readiness.set_proxy_bridge_ready();
+    Ok(primary)
+}
+
+/// Set up logging from `RUST_LOG` (default `info`), which supports per-module levels such as
+/// `info,scrappey_resolverr_rs::fwd_proxy=warn`. Noisy dependencies are limited to warnings
+/// unless `RUST_LOG` names them explicitly, e.g. `debug,thirtyfour=debug`.
+fn init_logging() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    for target in NOISY_LOG_TARGETS {
+        builder.filter_module(target, log::LevelFilter::Warn);
+    }
+    // Directives from RUST_LOG replace the defaults above for the same target
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    if let Ok(style) = std::env::var("RUST_LOG_STYLE") {
+        builder.parse_write_style(&style);
+    }
+    builder.init();
+}
+
+/// Periodically read the Scrappey balance and log how much was spent since the previous reading.
+async fn monitor_scrappey_balance(
+    client: ScrappeyClient,
+    metrics: Arc<Metrics>,
+    interval_mins: u64,
+    mut low_balance: LowBalanceAlert,
+) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_mins * 60));
+    interval.tick().await; // The first tick completes immediately, right after the startup check
+
+    let mut last_calls = metrics.scrappey_calls();
+    loop {
+        interval.tick().await;
+        let balance = match client.get_balance(30).await {
+            Ok(balance) => balance.balance,
+            Err(e) => {
+                error!("Failed to get Scrappey API balance: {e}");
+                continue;
+            }
+        };
+
+        let calls = metrics.scrappey_calls();
+        match metrics.record_balance(balance, chrono::Utc::now()) {
+            Some(previous) => info!(
+                "Scrappey API balance: {balance} ({} spent over {} Scrappey calls in the last {interval_mins} min)",
+                previous.balance - balance,
+                calls - last_calls
+            ),
+            None => info!("Scrappey API balance: {balance}"),
+        }
+        low_balance.check(balance);
+        last_calls = calls;
+    }
+}
+
+/// Warns about a Scrappey balance below the configured threshold, so that operators can top it up
+/// before fallbacks start failing.
+struct LowBalanceAlert {
+    /// 0 disables the alert.
+    threshold: f64,
+    webhook: Option<Webhook>,
+    /// Whether the previous reading was already low, so the webhook fires once per drop.
+    low: bool,
+}
+
+impl LowBalanceAlert {
+    fn new(threshold: f64, webhook: Option<Webhook>) -> Self {
+        Self {
+            threshold,
+            webhook,
+            low: false,
+        }
+    }
+
+    /// Check a balance reading. Every low reading is logged, but the webhook is only notified
+    /// when the balance drops below the threshold, not again until it has recovered.
+    fn check(&mut self, balance: f64) {
+        if self.threshold <= 0.0 {
+            return;
+        }
+        let was_low = std::mem::replace(&mut self.low, balance < self.threshold);
+        if !self.low {
+            if was_low {
+                info!("Scrappey API balance is back above {}", self.threshold);
+            }
+            return;
+        }
+
+        warn!(
+            "LOW SCRAPPEY BALANCE: {balance} is below the alert threshold of {}. Top up your \
+             Scrappey account before fallbacks start failing.",
+            self.threshold
+        );
+        if !was_low && let Some(webhook) = &self.webhook {
+            webhook.notify_low_balance(LowBalanceEvent {
+                event: "lowBalance".to_string(),
+                balance,
+                threshold: self.threshold,
+                timestamp: chrono::Utc::now().timestamp_millis(),
+            });
+        }
+    }
+}
+
+/// Make one throwaway navigation so the first real request doesn't pay for a cold browser and
+/// proxy connection. Persisted data is neither used nor updated, and failures are only logged.
+async fn warm_up_browser(config: &ServerConfig, url: &str) {
+    info!("Warming up the browser with {url}...");
+    let start = std::time::Instant::now();
+    let mut browser = browser::Browser::new().with_config(config.to_browser_config());
+    match browser.get(url, 30).await {
+        Ok(response) => info!(
+            "Browser warm-up finished in {}ms (status {})",
+            start.elapsed().as_millis(),
+            response.status
+        ),
+        Err(e) => warn!("Browser warm-up with {url} failed: {e}"),
+    }
 }
 
 /// Start the chromedriver process
@@ -80,10 +354,10 @@ fn start_chromedriver() -> Result<TransparentChild> {
     use std::process::Command;
     use transparent::{CommandExt, TransparentRunner};
 
-    let chromedriver = Command::new("/usr/bin/chromedriver")
+    let chromedriver = Command::new(CHROMEDRIVER_PATH)
         .arg("--port=9515")
         .spawn_transparent(&TransparentRunner::new())
-        .expect("Failed to start chromedriver");
+        .map_err(|e| anyhow::anyhow!("Failed to start chromedriver ({CHROMEDRIVER_PATH}): {e}"))?;
     Ok(chromedriver)
 }
 
@@ -111,30 +385,68 @@ async fn shutdown_signal() {
 
 /// Run the Axum server with graceful shutdown and chromedriver cleanup
 /// Run the Axum API server with graceful shutdown and chromedriver cleanup.
-/// Binds to the configured address, serves requests, and handles SIGINT/SIGTERM for shutdown.
-async fn run_server(config: ServerConfig, chromedriver: &mut std::process::Child) -> Result<()> {
+/// Binds to every configured address, serves the same router on each, and handles SIGINT/SIGTERM
+/// for shutdown of all listeners at once.
+async fn run_server(
+    config: ServerConfig,
+    readiness: Arc<Readiness>,
+    metrics: Arc<Metrics>,
+    bridge: Option<HttpProxyBridge>,
+    chromedriver: Option<&mut std::process::Child>,
+) -> Result<()> {
     use tokio::net::TcpListener;
-
-    let addr = config.bind_address();
-    info!("FlareSolverr starting on {addr}");
+    use tokio_util::sync::CancellationToken;
 
     // Create FlareSolverr API instance and router
-    let api = FlareSolverrAPI::new(config.clone());
+    let api = FlareSolverrAPI::new(config.clone(), readiness, metrics).with_bridge(bridge);
     let app = api.create_router();
 
-    // Create the TCP listener
-    let listener = TcpListener::bind(&addr).await?;
+    // A single shutdown signal stops every listener
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    // Create a TCP listener per address and start serving on each, with graceful shutdown
+    let mut servers = tokio::task::JoinSet::new();
+    for addr in config.bind_addresses() {
+        let listener = TcpListener::bind(&addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind API server to {addr}: {e}"))?;
+        info!("FlareSolverr starting on {addr}");
+        let server = axum::serve(listener, app.clone())
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+        servers.spawn(server.into_future());
+    }
 
-    // Start the server with graceful shutdown
-    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown_signal());
+    // Wait for all servers to finish. A failed listener stops the others, and its error is
+    // returned only after the cleanup below
+    let mut server_error = None;
+    while let Some(result) = servers.join_next().await {
+        let result: Result<()> = match result {
+            Ok(served) => served.map_err(Into::into),
+            Err(join_error) => Err(join_error.into()),
+        };
+        if let Err(e) = result {
+            error!("API server failed, shutting down: {e}");
+            shutdown.cancel();
+            server_error.get_or_insert(e);
+        }
+    }
 
-    // Wait for the server to finish
-    server.await?;
+    // Write persisted data still held in memory
+    api.flush_data().await;
 
     // Stop chromedriver when the server stops
-    if let Err(e) = chromedriver.kill() {
+    if let Some(chromedriver) = chromedriver
+        && let Err(e) = chromedriver.kill()
+    {
         error!("Failed to kill chromedriver: {e}");
     }
 
-    Ok(())
+    server_error.map_or(Ok(()), Err)
 }
@@ -0,0 +1,185 @@
+//! Runtime counters exposed on the `/metrics` endpoint.
+//! Every Scrappey fallback spends credits, so the main goal is to make that spend visible:
+//! how many calls were made, which hosts triggered them, and the balance burn rate over time.
+//! Proxy bandwidth is tracked too, per target host, from the bytes the proxy bridge relays.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A Scrappey balance reading.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceSample {
+    pub balance: f64,
+    /// Time of the reading, in milliseconds since the Unix epoch.
+    pub timestamp: i64,
+}
+
+/// Shared metrics registry.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    scrappey_calls: AtomicU64,
+    scrappey_failures: AtomicU64,
+    scrappey_calls_by_host: Mutex<HashMap<String, u64>>,
+    /// Start of the current Scrappey rate limit window and the calls made in it.
+    scrappey_window: Mutex<Option<(Instant, u64)>>,
+    /// Scrappey calls refused because the rate limit was reached.
+    scrappey_rate_limited: AtomicU64,
+    /// First and most recent balance readings.
+    balance: Mutex<Option<(BalanceSample, BalanceSample)>>,
+    bridge_traffic_by_host: Mutex<HashMap<String, HostTraffic>>,
+}
+
+/// Traffic the proxy bridge relayed for one target host.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostTraffic {
+    pub connections: u64,
+    /// Bytes from the browser to the target.
+    pub bytes_sent: u64,
+    /// Bytes from the target to the browser.
+    pub bytes_received: u64,
+}
+
+/// Scrappey usage section of the `/metrics` response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScrappeyMetrics {
+    pub calls: u64,
+    pub failures: u64,
+    /// Fallback calls per target host, to spot which site is eating the balance.
+    pub calls_by_host: HashMap<String, u64>,
+    /// Calls in the current rate limit window (`SCRAPPEY_MAX_CALLS`).
+    pub calls_in_window: u64,
+    /// Calls refused because the rate limit was reached.
+    pub rate_limited: u64,
+    pub initial_balance: Option<BalanceSample>,
+    pub latest_balance: Option<BalanceSample>,
+    /// Balance spent between the first and latest readings.
+    pub credits_used: Option<f64>,
+    /// Average balance spent per hour between the first and latest readings.
+    pub credits_per_hour: Option<f64>,
+}
+
+/// Proxy bridge section of the `/metrics` response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BridgeMetrics {
+    /// Totals of tunnels that have closed and plain requests that were relayed, per target host.
+    pub traffic_by_host: HashMap<String, HostTraffic>,
+}
+
+/// Body of the `/metrics` response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub scrappey: ScrappeyMetrics,
+    pub bridge: BridgeMetrics,
+}
+
+impl Metrics {
+    /// Record a Scrappey fallback call for the given target URL.
+    pub fn record_scrappey_call(&self, url: &str) {
+        self.scrappey_calls.fetch_add(1, Ordering::Relaxed);
+        let host = url::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_string))
+            .unwrap_or_else(|| "unknown".to_string());
+        *self
+            .scrappey_calls_by_host
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_default() += 1;
+    }
+
+    /// Count a Scrappey call against the limit of `max_calls` per `window` (0 for no limit).
+    /// Returns false, without counting it, if the current window's calls are used up.
+    pub fn reserve_scrappey_call(&self, max_calls: u64, window: Duration) -> bool {
+        let mut current = self.scrappey_window.lock().unwrap();
+        let (started, calls) = match *current {
+            Some((started, calls)) if started.elapsed() < window => (started, calls),
+            _ => (Instant::now(), 0),
+        };
+        if max_calls > 0 && calls >= max_calls {
+            self.scrappey_rate_limited.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        *current = Some((started, calls + 1));
+        true
+    }
+
+    /// Record a failed Scrappey fallback call.
+    pub fn record_scrappey_failure(&self) {
+        self.scrappey_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a balance reading, returning the previous latest reading if any.
+    pub fn record_balance(&self, balance: f64, at: DateTime<Utc>) -> Option<BalanceSample> {
+        let sample = BalanceSample {
+            balance,
+            timestamp: at.timestamp_millis(),
+        };
+        let mut readings = self.balance.lock().unwrap();
+        let previous = readings.map(|(_, latest)| latest);
+        *readings = Some(match *readings {
+            Some((initial, _)) => (initial, sample),
+            None => (sample, sample),
+        });
+        previous
+    }
+
+    /// Most recent balance reading, if any.
+    pub fn latest_balance(&self) -> Option<BalanceSample> {
+        self.balance.lock().unwrap().map(|(_, latest)| latest)
+    }
+
+    /// Record a closed proxy bridge tunnel or a relayed plain request to the given target host,
+    /// and the bytes it relayed.
+    pub fn record_bridge_traffic(&self, host: &str, bytes_sent: u64, bytes_received: u64) {
+        let mut traffic = self.bridge_traffic_by_host.lock().unwrap();
+        let totals = traffic.entry(host.to_string()).or_default();
+        totals.connections += 1;
+        totals.bytes_sent += bytes_sent;
+        totals.bytes_received += bytes_received;
+    }
+
+    /// Total Scrappey calls made so far.
+    pub fn scrappey_calls(&self) -> u64 {
+        self.scrappey_calls.load(Ordering::Relaxed)
+    }
+
+    /// Take a consistent-enough snapshot of all metrics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let readings = *self.balance.lock().unwrap();
+        let credits_used = readings.map(|(initial, latest)| initial.balance - latest.balance);
+        let credits_per_hour = readings.and_then(|(initial, latest)| {
+            let hours = (latest.timestamp - initial.timestamp) as f64 / 3_600_000.0;
+            (hours > 0.0).then(|| (initial.balance - latest.balance) / hours)
+        });
+
+        MetricsSnapshot {
+            scrappey: ScrappeyMetrics {
+                calls: self.scrappey_calls(),
+                failures: self.scrappey_failures.load(Ordering::Relaxed),
+                calls_by_host: self.scrappey_calls_by_host.lock().unwrap().clone(),
+                calls_in_window: self
+                    .scrappey_window
+                    .lock()
+                    .unwrap()
+                    .map_or(0, |(_, calls)| calls),
+                rate_limited: self.scrappey_rate_limited.load(Ordering::Relaxed),
+                initial_balance: readings.map(|(initial, _)| initial),
+                latest_balance: readings.map(|(_, latest)| latest),
+                credits_used,
+                credits_per_hour,
+            },
+            bridge: BridgeMetrics {
+                traffic_by_host: self.bridge_traffic_by_host.lock().unwrap().clone(),
+            },
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Startup readiness tracking for the `/ready` endpoint.
+//! The API server starts serving right away, but requests can only succeed once the proxy bridge
+//! is bound and chromedriver answers, so orchestrators can gate traffic on this instead of `/health`.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Readiness flags of the subsystems started in `main`.
+#[derive(Debug, Default)]
+pub struct Readiness {
+    proxy_bridge: AtomicBool,
+    chromedriver: AtomicBool,
+}
+
+/// State of a single subsystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SubsystemState {
+    Starting,
+    Ready,
+}
+
+impl From<bool> for SubsystemState {
+    fn from(ready: bool) -> Self {
+        if ready {
+            SubsystemState::Ready
+        } else {
+            SubsystemState::Starting
+        }
+    }
+}
+
+/// Body of the `/ready` response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub proxy_bridge: SubsystemState,
+    pub chromedriver: SubsystemState,
+}
+
+impl Readiness {
+    /// Mark the proxy bridge as bound.
+    pub fn set_proxy_bridge_ready(&self) {
+        self.proxy_bridge.store(true, Ordering::Release);
+    }
+
+    /// Mark chromedriver as responding.
+    pub fn set_chromedriver_ready(&self) {
+        self.chromedriver.store(true, Ordering::Release);
+    }
+
+    /// Returns true once chromedriver has responded.
+    pub fn is_chromedriver_ready(&self) -> bool {
+        self.chromedriver.load(Ordering::Acquire)
+    }
+
+    /// Snapshot the state of all subsystems.
+    pub fn report(&self) -> ReadinessReport {
+        let proxy_bridge = self.proxy_bridge.load(Ordering::Acquire);
+        let chromedriver = self.chromedriver.load(Ordering::Acquire);
+        ReadinessReport {
+            ready: proxy_bridge && chromedriver,
+            proxy_bridge: proxy_bridge.into(),
+            chromedriver: chromedriver.into(),
+        }
+    }
+}
+
+/// Poll chromedriver's `/status` endpoint until it reports ready, then mark it as such.
+pub async fn wait_for_chromedriver(webdriver_url: String, readiness: std::sync::Arc<Readiness>) {
+    let client = reqwest::Client::new();
+    while !chromedriver_status_ready(&client, &webdriver_url).await {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    log::info!("Chromedriver is ready");
+    readiness.set_chromedriver_ready();
+}
+
+/// Poll chromedriver's `/status` endpoint for up to `within`, e.g. after losing the connection
+/// to it mid-request. Returns true if it reported ready in time.
+pub async fn chromedriver_ready_within(webdriver_url: &str, within: Duration) -> bool {
+    let client = reqwest::Client::new();
+    let deadline = tokio::time::Instant::now() + within;
+    loop {
+        if chromedriver_status_ready(&client, webdriver_url).await {
+            return true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// Ask chromedriver once whether it is ready to create sessions.
+async fn chromedriver_status_ready(client: &reqwest::Client, webdriver_url: &str) -> bool {
+    let status_url = format!("{}/status", webdriver_url.trim_end_matches('/'));
+    match client
+        .get(&status_url)
+        .timeout(Duration::from_secs(2))
+        .send()
+        .await
+    {
+        Ok(resp) => resp
+            .json::<serde_json::Value>()
+            .await
+            .is_ok_and(|status| status["value"]["ready"] == true),
+        Err(_) => false,
+    }
+}
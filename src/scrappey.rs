@@ -8,7 +8,63 @@ use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
-use thirtyfour::{Cookie, SameSite};
+use thirtyfour::Cookie;
+
+use crate::config::{MinTlsVersion, ScrappeyConfig};
+use crate::cookies;
+
+/// Scrappey rejected the configured API key, with the reason it gave.
+#[derive(Debug)]
+pub struct InvalidApiKey(String);
+
+impl std::fmt::Display for InvalidApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Scrappey rejected the API key: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidApiKey {}
+
+/// Scrappey answered with an error object (e.g. `{"error": "..."}`) instead of the expected data.
+#[derive(Debug)]
+pub struct ScrappeyApiError {
+    pub status: reqwest::StatusCode,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScrappeyApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Scrappey returned an error (status {}): {}",
+            self.status,
+            truncate_str(&self.message, ERROR_SNIPPET_CHARS)
+        )
+    }
+}
+
+impl std::error::Error for ScrappeyApiError {}
+
+/// A Scrappey solve failed, for the reason wrapped (e.g. a `ScrappeyApiError` or a request error).
+#[derive(Debug)]
+pub struct ScrappeyFailed(pub anyhow::Error);
+
+impl std::fmt::Display for ScrappeyFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for ScrappeyFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.0.as_ref())
+    }
+}
+
+/// Largest response body accepted from Scrappey, to bound memory use on huge pages.
+const MAX_RESPONSE_BYTES: usize = 50 * 1024 * 1024;
+/// How much of an unparseable response body to include in the error.
+const ERROR_SNIPPET_CHARS: usize = 200;
 
 /// Client for interacting with the Scrappey API.
 #[derive(Debug, Clone)]
@@ -28,7 +84,29 @@ impl ScrappeyClient {
         }
     }
 
+    /// Create a ScrappeyClient from the configuration, routing API calls through the configured
+    /// API proxy (if any) and refusing TLS versions older than the configured minimum.
+    pub fn from_config(config: &ScrappeyConfig) -> Result<Self> {
+        let mut builder = Client::builder().min_tls_version(config.min_tls_version.into());
+        if config.min_tls_version == MinTlsVersion::Tls13 {
+            // native-tls cannot enforce a TLS 1.3 minimum, rustls can
+            builder = builder.use_rustls_tls();
+        }
+        if let Some(proxy) = &config.api_proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| anyhow::anyhow!("Failed to build the Scrappey HTTP client: {e}"))?;
+
+        Ok(Self {
+            client,
+            ..Self::new(config.api_key.clone())
+        })
+    }
+
     /// Check remaining balance (number of requests left) on the Scrappey account.
+    /// Fails with `InvalidApiKey` if Scrappey rejects the key.
     pub async fn get_balance(&self, timeout: u64) -> Result<ScrappeyBalance> {
         let resp = self
             .client
@@ -36,11 +114,17 @@ impl ScrappeyClient {
             .header("Content-Type", "application/json")
             .timeout(std::time::Duration::from_secs(timeout))
             .send()
-            .await?;
-
-        resp.json()
             .await
-            .map_err(|e| anyhow::anyhow!("Failed to parse balance response: {}", e))
+            .map_err(reqwest::Error::without_url)?;
+
+        let status = resp.status();
+        read_json(resp).await.map_err(|e| {
+            if rejects_key(status, &e) {
+                InvalidApiKey(e.to_string()).into()
+            } else {
+                e
+            }
+        })
     }
 
     /// Make a GET request via Scrappey, using the provided parameters and timeout.
@@ -54,10 +138,9 @@ impl ScrappeyClient {
             .json(&payload)
             .timeout(std::time::Duration::from_secs(timeout))
             .send()
-            .await?;
-        resp.json()
             .await
-            .map_err(|e| anyhow::anyhow!("Failed to parse Scrappey response: {}", e))
+            .map_err(reqwest::Error::without_url)?;
+        read_json(resp).await
     }
 
     /// Make a POST request via Scrappey, using the provided parameters and timeout.
@@ -71,10 +154,96 @@ impl ScrappeyClient {
             .json(&payload)
             .timeout(std::time::Duration::from_secs(timeout))
             .send()
-            .await?;
-        resp.json()
             .await
-            .map_err(|e| anyhow::anyhow!("Failed to parse Scrappey response: {}", e))
+            .map_err(reqwest::Error::without_url)?;
+        read_json(resp).await
+    }
+}
+
+/// Read a JSON response body, streaming it with a size cap.
+/// Non-JSON bodies (e.g. an HTML 502 page from a CDN in front of Scrappey) produce an error
+/// with the HTTP status and a snippet of the body rather than a bare parse error.
+async fn read_json<T: serde::de::DeserializeOwned>(mut resp: reqwest::Response) -> Result<T> {
+    let status = resp.status();
+    if resp
+        .content_length()
+        .is_some_and(|len| len > MAX_RESPONSE_BYTES as u64)
+    {
+        return Err(anyhow::anyhow!(
+            "Scrappey response too large (status {status}, {} bytes, limit {MAX_RESPONSE_BYTES})",
+            resp.content_length().unwrap_or_default()
+        ));
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(reqwest::Error::without_url)? {
+        if body.len() + chunk.len() > MAX_RESPONSE_BYTES {
+            return Err(anyhow::anyhow!(
+                "Scrappey response too large (status {status}, over {MAX_RESPONSE_BYTES} bytes)"
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    parse_json(status, &body)
+}
+
+/// Parse a response body read by `read_json`.
+fn parse_json<T: serde::de::DeserializeOwned>(
+    status: reqwest::StatusCode,
+    body: &[u8],
+) -> Result<T> {
+    serde_json::from_slice(body).map_err(|e| {
+        let text = String::from_utf8_lossy(body);
+        if let Ok(value) = serde_json::from_slice::<Value>(body) {
+            // Valid JSON, just not the shape we expected: usually an error object without a solution
+            match error_message(&value) {
+                Some(message) => ScrappeyApiError { status, message }.into(),
+                None => anyhow::anyhow!("Unexpected Scrappey response (status {status}): {e}"),
+            }
+        } else {
+            anyhow::anyhow!(
+                "Non-JSON response from Scrappey (status {status}): {}",
+                truncate_str(text.trim(), ERROR_SNIPPET_CHARS)
+            )
+        }
+    })
+}
+
+/// Whether a failed balance check means that the key was rejected: an authentication status, or
+/// an error object from Scrappey about a key (the endpoint's only parameter). Other failures, such
+/// as a CDN's error page, say nothing about the key.
+fn rejects_key(status: reqwest::StatusCode, error: &anyhow::Error) -> bool {
+    matches!(status.as_u16(), 401 | 403)
+        || error
+            .downcast_ref::<ScrappeyApiError>()
+            .is_some_and(|e| e.message.to_ascii_lowercase().contains("key"))
+}
+
+/// Error message of a Scrappey error object, e.g. `{"error": "..."}`.
+fn error_message(value: &Value) -> Option<String> {
+    ["error", "message", "info"]
+        .iter()
+        .find_map(|field| match &value[field] {
+            Value::Null => None,
+            Value::String(message) => Some(message.clone()),
+            other => Some(other.to_string()),
+        })
+}
+
+/// Truncate a string to at most `max_chars` characters, marking the cut with an ellipsis.
+pub fn truncate_str(s: &str, max_chars: usize) -> String {
+    match s.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}…", &s[..idx]),
+        None => s.to_string(),
+    }
+}
+
+/// Truncate a logged string field to `max_chars` characters, or keep it whole if that is 0.
+pub fn truncate_for_log(s: &str, max_chars: usize) -> String {
+    if max_chars == 0 {
+        s.to_string()
+    } else {
+        truncate_str(s, max_chars)
     }
 }
 
@@ -153,8 +322,9 @@ pub struct ScrappeyCookie {
     pub value: String,
     pub domain: String,
     pub path: String,
+    /// Expiry as a Unix timestamp in seconds, possibly fractional; `-1` for session cookies.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub expires: Option<i64>,
+    pub expires: Option<f64>,
     #[serde(rename = "httpOnly", skip_serializing_if = "Option::is_none")]
     pub http_only: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -172,13 +342,8 @@ impl From<ScrappeyCookie> for Cookie {
             path: Some(scr.path),
             domain: Some(scr.domain),
             secure: scr.secure,
-            expiry: scr.expires,
-            same_site: scr.same_site.and_then(|s| match s.to_lowercase().as_str() {
-                "lax" => Some(SameSite::Lax),
-                "strict" => Some(SameSite::Strict),
-                "none" => Some(SameSite::None),
-                _ => None,
-            }),
+            expiry: scr.expires.and_then(cookies::expiry_from_secs),
+            same_site: scr.same_site.as_deref().and_then(cookies::parse_same_site),
         }
     }
 }
@@ -194,6 +359,24 @@ pub struct ScrappeyResponse {
     pub session: Option<String>,
 }
 
+impl ScrappeyResponse {
+    /// Copy of this response for logging, with the page body and other long fields truncated.
+    pub fn for_log(&self, max_chars: usize) -> Self {
+        let cut = |field: &Option<String>| {
+            field
+                .as_deref()
+                .map(|value| truncate_for_log(value, max_chars))
+        };
+        let mut response = self.clone();
+        response.data = cut(&self.data);
+        response.solution.response = cut(&self.solution.response);
+        response.solution.inner_text = cut(&self.solution.inner_text);
+        response.solution.request_body = cut(&self.solution.request_body);
+        response.solution.cookie_string = cut(&self.solution.cookie_string);
+        response
+    }
+}
+
 /// Solution object returned by Scrappey for a challenge-solving request.
 /// Contains cookies, user agent, response body, and other metadata.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -225,3 +408,102 @@ pub struct ScrappeySolution {
     #[serde(rename = "type")]
     pub r#type: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn api_error(status: StatusCode, message: &str) -> anyhow::Error {
+        ScrappeyApiError {
+            status,
+            message: message.to_string(),
+        }
+        .into()
+    }
+
+    fn scrappey_cookie(expires: Option<f64>) -> ScrappeyCookie {
+        ScrappeyCookie {
+            name: "cf_clearance".to_string(),
+            value: "token".to_string(),
+            domain: ".example.com".to_string(),
+            path: "/".to_string(),
+            expires,
+            http_only: Some(true),
+            secure: Some(true),
+            same_site: Some("None".to_string()),
+        }
+    }
+
+    #[test]
+    fn scrappey_cookie_converts_expiry_and_same_site() {
+        let cookie = Cookie::from(scrappey_cookie(Some(1_700_000_000.0)));
+        assert_eq!(cookie.expiry, Some(1_700_000_000));
+        assert!(matches!(cookie.same_site, Some(thirtyfour::SameSite::None)));
+        assert_eq!(Cookie::from(scrappey_cookie(None)).expiry, None);
+    }
+
+    #[test]
+    fn auth_statuses_reject_the_key() {
+        let error = anyhow::anyhow!("Non-JSON response from Scrappey (status 403): Forbidden");
+        assert!(rejects_key(StatusCode::FORBIDDEN, &error));
+        assert!(rejects_key(StatusCode::UNAUTHORIZED, &error));
+    }
+
+    #[test]
+    fn scrappey_key_errors_reject_the_key() {
+        let error = api_error(StatusCode::OK, "Invalid API key");
+        assert!(rejects_key(StatusCode::OK, &error));
+    }
+
+    #[test]
+    fn other_errors_mentioning_a_key_do_not_reject_it() {
+        let page = anyhow::anyhow!(
+            "Non-JSON response from Scrappey (status 502): <html>Bad gateway, see our keyboard</html>"
+        );
+        assert!(!rejects_key(StatusCode::BAD_GATEWAY, &page));
+        let error = api_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal error");
+        assert!(!rejects_key(StatusCode::INTERNAL_SERVER_ERROR, &error));
+    }
+
+    #[test]
+    fn error_object_becomes_an_api_error() {
+        let body = br#"{"error": "Not enough balance", "solution": null}"#;
+        let error = parse_json::<ScrappeyResponse>(StatusCode::OK, body).unwrap_err();
+        let api_error = error.downcast_ref::<ScrappeyApiError>().unwrap();
+        assert_eq!(api_error.message, "Not enough balance");
+        assert_eq!(
+            error.to_string(),
+            "Scrappey returned an error (status 200 OK): Not enough balance"
+        );
+    }
+
+    #[test]
+    fn html_error_page_is_reported_with_its_status() {
+        let body = b"<html><body><h1>502 Bad Gateway</h1></body></html>";
+        let error = parse_json::<ScrappeyResponse>(StatusCode::BAD_GATEWAY, body).unwrap_err();
+        assert!(error.downcast_ref::<ScrappeyApiError>().is_none());
+        assert_eq!(
+            error.to_string(),
+            "Non-JSON response from Scrappey (status 502 Bad Gateway): \
+             <html><body><h1>502 Bad Gateway</h1></body></html>"
+        );
+    }
+
+    #[test]
+    fn truncation_cuts_at_char_boundaries() {
+        // 2-, 3- and 4-byte characters
+        assert_eq!(truncate_str("héllo", 2), "hé…");
+        assert_eq!(truncate_str("a€b", 2), "a€…");
+        assert_eq!(truncate_str("🦀🦀🦀", 1), "🦀…");
+        assert_eq!(truncate_str("🦀🦀", 2), "🦀🦀");
+        assert_eq!(truncate_str("", 0), "");
+    }
+
+    #[test]
+    fn zero_log_limit_keeps_the_whole_field() {
+        let field = "é€🦀".repeat(1000);
+        assert_eq!(truncate_for_log(&field, 0), field);
+        assert_eq!(truncate_for_log(&field, 3), "é€🦀…");
+    }
+}
@@ -0,0 +1,100 @@
+//! Cleanup of failure screenshots.
+//! Screenshots are only useful for a while, so they're pruned by count, age and total size to keep
+//! `SCREENSHOT_DIR` from filling the disk when capture is left enabled.
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::config::ScreenshotConfig;
+
+/// How often the background task prunes the screenshot directory.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A failure screenshot on disk.
+struct Screenshot {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+/// Prune the screenshot directory at startup and then periodically.
+pub async fn run_cleanup(config: ScreenshotConfig) {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = cleanup(&config) {
+            warn!("Failed to clean up failure screenshots: {e}");
+        }
+    }
+}
+
+/// Delete failure screenshots beyond the configured count, age or total size, oldest first.
+pub fn cleanup(config: &ScreenshotConfig) -> Result<()> {
+    let mut screenshots = list(config)?;
+    // Newest first, so that everything past the first limit reached is removed
+    screenshots.sort_by_key(|screenshot| std::cmp::Reverse(screenshot.modified));
+
+    let max_age = Duration::from_secs(config.max_age_days * 24 * 60 * 60);
+    let now = SystemTime::now();
+    let mut total_bytes = 0;
+    let keep = screenshots
+        .iter()
+        .take_while(|screenshot| {
+            total_bytes += screenshot.size;
+            let expired = config.max_age_days > 0
+                && now
+                    .duration_since(screenshot.modified)
+                    .is_ok_and(|age| age > max_age);
+            let oversized = config.max_total_bytes > 0 && total_bytes > config.max_total_bytes;
+            !expired && !oversized
+        })
+        .count()
+        .min(config.max_failure_screenshots);
+
+    let to_remove = &screenshots[keep..];
+    for screenshot in to_remove {
+        match std::fs::remove_file(&screenshot.path) {
+            Ok(()) => debug!("Removed old screenshot: {}", screenshot.path.display()),
+            Err(e) => warn!(
+                "Failed to remove old screenshot {}: {e}",
+                screenshot.path.display()
+            ),
+        }
+    }
+    if !to_remove.is_empty() {
+        info!(
+            "Cleaned up {} old failure screenshots, keeping {keep} most recent",
+            to_remove.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Failure screenshots (`failure_*.png`) in the screenshot directory.
+fn list(config: &ScreenshotConfig) -> Result<Vec<Screenshot>> {
+    let dir = std::path::Path::new(&config.screenshot_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(std::fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let path = entry.path();
+            let is_screenshot = path.extension().and_then(|s| s.to_str()) == Some("png")
+                && path
+                    .file_name()
+                    .and_then(|s| s.to_str())
+                    .is_some_and(|name| name.starts_with("failure_"));
+            let metadata = entry.metadata().ok()?;
+            (is_screenshot && metadata.is_file()).then_some(Screenshot {
+                path,
+                modified: metadata.modified().ok()?,
+                size: metadata.len(),
+            })
+        })
+        .collect())
+}
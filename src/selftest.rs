@@ -0,0 +1,148 @@
+//! End-to-end self-test for validating a deployment.
+//! Unlike `/health`, this actually drives the moving parts: the proxy bridge and its upstream,
+//! a real browser navigation through the bridge, and the Scrappey API.
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::browser::Browser;
+use crate::config::ServerConfig;
+use crate::scrappey::ScrappeyClient;
+
+/// Result of checking a single component.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentStatus {
+    pub healthy: bool,
+    pub message: String,
+    pub duration_ms: u64,
+}
+
+/// Structured report of which components are healthy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelfTestReport {
+    pub status: String,
+    pub proxy: ComponentStatus,
+    pub browser: ComponentStatus,
+    pub scrappey: ComponentStatus,
+}
+
+/// Run all checks and build the report.
+pub async fn run(config: &ServerConfig) -> SelfTestReport {
+    info!("Running self-test against {}", config.selftest_url);
+
+    let proxy = if config.proxy.is_direct() {
+        timed(check_direct_proxy(&config.proxy.host, config.proxy.port)).await
+    } else {
+        let bridge = std::net::SocketAddr::new(config.bridge.connect_address(), config.bridge.port);
+        timed(check_proxy(&config.selftest_url, bridge)).await
+    };
+    let browser = timed(check_browser(config)).await;
+    let scrappey = timed(check_scrappey(config)).await;
+
+    let healthy = proxy.healthy && browser.healthy && scrappey.healthy;
+    SelfTestReport {
+        status: if healthy { "ok" } else { "error" }.to_string(),
+        proxy,
+        browser,
+        scrappey,
+    }
+}
+
+/// Time a check and turn its outcome into a component status.
+async fn timed(check: impl Future<Output = Result<String, String>>) -> ComponentStatus {
+    let start = Instant::now();
+    let result = check.await;
+    let duration_ms = start.elapsed().as_millis() as u64;
+    match result {
+        Ok(message) => ComponentStatus {
+            healthy: true,
+            message,
+            duration_ms,
+        },
+        Err(message) => ComponentStatus {
+            healthy: false,
+            message,
+            duration_ms,
+        },
+    }
+}
+
+/// Open a CONNECT tunnel through the local bridge, which exercises the upstream proxy and its auth.
+async fn check_proxy(url: &str, bridge: std::net::SocketAddr) -> Result<String, String> {
+    let url = url::Url::parse(url).map_err(|e| format!("Invalid self-test URL: {e}"))?;
+    let target = format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or(443)
+    );
+
+    let mut stream = TcpStream::connect(bridge)
+        .await
+        .map_err(|e| format!("Proxy bridge is not reachable: {e}"))?;
+    stream
+        .write_all(format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await
+        .map_err(|e| format!("Failed to write to proxy bridge: {e}"))?;
+
+    let mut status_line = String::new();
+    BufReader::new(&mut stream)
+        .read_line(&mut status_line)
+        .await
+        .map_err(|e| format!("Failed to read from proxy bridge: {e}"))?;
+
+    if status_line.contains("200") {
+        Ok(format!("Tunnel to {target} established"))
+    } else {
+        Err(format!(
+            "Upstream proxy refused tunnel to {target}: {}",
+            status_line.trim()
+        ))
+    }
+}
+
+/// Check that a proxy the browser uses directly (SOCKS5, no bridge) accepts connections.
+/// Whether it can reach the target is covered by the browser check.
+async fn check_direct_proxy(host: &str, port: u16) -> Result<String, String> {
+    TcpStream::connect((host, port))
+        .await
+        .map_err(|e| format!("SOCKS5 proxy {host}:{port} is not reachable: {e}"))?;
+    Ok(format!("SOCKS5 proxy {host}:{port} is reachable"))
+}
+
+/// Navigate a fresh browser (not touching persisted data) to the self-test URL.
+async fn check_browser(config: &ServerConfig) -> Result<String, String> {
+    let mut browser = Browser::new().with_config(config.to_browser_config());
+    let response = browser
+        .get(&config.selftest_url, 30)
+        .await
+        .map_err(|e| format!("Browser navigation failed: {e}"))?;
+
+    if response.status != 200 {
+        return Err(format!("Browser got status {}", response.status));
+    }
+    if response.cookies.is_empty() {
+        return Err("Browser got status 200 but no cookies".to_string());
+    }
+    Ok(format!(
+        "Browser got status 200 and {} cookies",
+        response.cookies.len()
+    ))
+}
+
+/// Confirm the Scrappey balance can be read with the configured key.
+async fn check_scrappey(config: &ServerConfig) -> Result<String, String> {
+    if !config.scrappey.is_configured() {
+        return Err("Scrappey API key not configured".to_string());
+    }
+    let client = ScrappeyClient::from_config(&config.scrappey)
+        .map_err(|e| format!("Invalid Scrappey client configuration: {e}"))?;
+    let balance = client
+        .get_balance(30)
+        .await
+        .map_err(|e| format!("Failed to read Scrappey balance: {e}"))?;
+    Ok(format!("Scrappey balance: {}", balance.balance))
+}
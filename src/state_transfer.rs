@@ -0,0 +1,211 @@
+//! Export and import of all browser state, for moving hard-won clearance between instances or
+//! backing it up without copying `persistent.json` out of the container by hand.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::browser::BrowserData;
+use crate::config::ServerConfig;
+
+/// All browser state: the persisted default data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateSnapshot {
+    /// Data used by requests (`persistent.json`). None if persistence is disabled or nothing has
+    /// been saved yet.
+    #[serde(default)]
+    pub default: Option<BrowserData>,
+}
+
+/// How imported state is combined with the existing state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Imported cookies override existing ones with the same name, domain and path, and the
+    /// imported user agent replaces the existing one.
+    #[default]
+    Merge,
+    /// Discard all existing state first.
+    Replace,
+}
+
+/// Summary of an import, returned by `POST /state/import`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub mode: ImportMode,
+    pub default_imported: bool,
+}
+
+/// Collect the current state.
+pub fn export(config: &ServerConfig) -> Result<StateSnapshot> {
+    let default = if config.cookies.persist_data {
+        match std::fs::read_to_string(&config.data_path) {
+            Ok(contents) => Some(serde_json::from_str(&contents).map_err(|e| {
+                anyhow::anyhow!("Invalid persisted data in {}: {e}", config.data_path)
+            })?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to read persisted data from {}: {e}",
+                    config.data_path
+                ));
+            }
+        }
+    } else {
+        None
+    };
+
+    Ok(StateSnapshot { default })
+}
+
+/// Apply an imported snapshot to the persisted default data.
+pub fn import(
+    config: &ServerConfig,
+    snapshot: StateSnapshot,
+    mode: ImportMode,
+) -> Result<ImportReport> {
+    validate(&snapshot)?;
+
+    let default_imported = match snapshot.default {
+        Some(_) if !config.cookies.persist_data => {
+            return Err(anyhow::anyhow!(
+                "Cannot import default data: data persistence is disabled (PERSIST_DATA=false)"
+            ));
+        }
+        Some(mut imported) => {
+            if mode == ImportMode::Merge
+                && let Ok(contents) = std::fs::read_to_string(&config.data_path)
+                && let Ok(existing) = serde_json::from_str::<BrowserData>(&contents)
+            {
+                imported.cookies = merge_cookies(existing.cookies, imported.cookies);
+            }
+            imported.saved_at = Some(chrono::Utc::now().timestamp());
+            let file = std::fs::File::create(&config.data_path).map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to write persisted data to {}: {e}",
+                    config.data_path
+                )
+            })?;
+            serde_json::to_writer_pretty(file, &imported)?;
+            true
+        }
+        None => {
+            if mode == ImportMode::Replace && config.cookies.persist_data {
+                match std::fs::remove_file(&config.data_path) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        return Err(anyhow::anyhow!(
+                            "Failed to remove persisted data {}: {e}",
+                            config.data_path
+                        ));
+                    }
+                }
+            }
+            false
+        }
+    };
+
+    Ok(ImportReport {
+        mode,
+        default_imported,
+    })
+}
+
+/// Reject snapshots that would leave a browser without a usable identity.
+fn validate(snapshot: &StateSnapshot) -> Result<()> {
+    if let Some(data) = &snapshot.default {
+        if data.user_agent.trim().is_empty() {
+            return Err(anyhow::anyhow!(
+                "Invalid state: default data has no user agent"
+            ));
+        }
+        if data.cookies.iter().any(|cookie| cookie.name.is_empty()) {
+            return Err(anyhow::anyhow!(
+                "Invalid state: default data has a cookie without a name"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Identity of a cookie: two cookies with the same name, domain and path are the same cookie.
+/// The domain is compared case-insensitively and without its leading dot (`.example.com` and
+/// `example.com` are the same), and a missing path is `/`.
+fn cookie_identity(cookie: &thirtyfour::Cookie) -> (String, String, String) {
+    (
+        cookie.name.clone(),
+        cookie
+            .domain
+            .as_deref()
+            .unwrap_or_default()
+            .trim_start_matches('.')
+            .to_ascii_lowercase(),
+        cookie.path.clone().unwrap_or_else(|| "/".to_string()),
+    )
+}
+
+/// Combine two cookie lists, with `new` cookies replacing existing ones with the same identity
+/// (see `cookie_identity`). This is the one place cookie lists are merged: imports, Scrappey
+/// solves, concurrent saves and `forceCookies` all go through it.
+pub fn merge_cookies(
+    existing: Vec<thirtyfour::Cookie>,
+    new: Vec<thirtyfour::Cookie>,
+) -> Vec<thirtyfour::Cookie> {
+    let replaced: std::collections::HashSet<_> = new.iter().map(cookie_identity).collect();
+    let mut cookies: Vec<_> = existing
+        .into_iter()
+        .filter(|cookie| !replaced.contains(&cookie_identity(cookie)))
+        .collect();
+    cookies.extend(new);
+    cookies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use thirtyfour::Cookie;
+
+    fn cookie(name: &str, value: &str, domain: &str) -> Cookie {
+        let mut cookie = Cookie::new(name, value);
+        cookie.set_domain(domain);
+        cookie.set_path("/");
+        cookie
+    }
+
+    #[test]
+    fn merged_cookies_replace_ones_with_the_same_name_domain_and_path() {
+        let existing = vec![
+            cookie("cf_clearance", "old", ".example.com"),
+            cookie("session", "kept", ".example.com"),
+        ];
+        let new = vec![
+            cookie("cf_clearance", "new", ".example.com"),
+            cookie("cf_clearance", "other", ".other.com"),
+        ];
+
+        let merged = merge_cookies(existing, new.clone());
+        assert_eq!(merged.len(), 3);
+        let clearance: Vec<_> = merged
+            .iter()
+            .filter(|c| c.name == "cf_clearance" && c.domain.as_deref() == Some(".example.com"))
+            .collect();
+        assert_eq!(clearance.len(), 1);
+        assert_eq!(clearance[0].value, "new");
+
+        // Merging the same cookies again doesn't add duplicates
+        assert_eq!(merge_cookies(merged, new).len(), 3);
+    }
+
+    #[test]
+    fn cookie_identity_ignores_domain_case_leading_dot_and_default_path() {
+        let existing = vec![cookie("cf_clearance", "old", ".Example.com")];
+        let mut new = Cookie::new("cf_clearance", "new");
+        new.set_domain("example.com");
+
+        let merged = merge_cookies(existing, vec![new]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].value, "new");
+    }
+}
@@ -0,0 +1,36 @@
+// Default preload script, run before any of the page's own scripts.
+// Hides the most common automation tells that anti-bot scripts probe for.
+
+// navigator.webdriver is true under automation
+Object.defineProperty(Navigator.prototype, 'webdriver', {
+  get: () => undefined,
+  configurable: true,
+});
+
+// Regular Chrome exposes window.chrome, which headless/automated sessions may lack
+if (!window.chrome) {
+  window.chrome = {};
+}
+if (!window.chrome.runtime) {
+  window.chrome.runtime = {};
+}
+
+// Automated sessions report 'denied' for notifications while Notification.permission is 'default'
+if (navigator.permissions && navigator.permissions.query) {
+  const originalQuery = navigator.permissions.query.bind(navigator.permissions);
+  navigator.permissions.query = (parameters) =>
+    parameters && parameters.name === 'notifications'
+      ? Promise.resolve({ state: Notification.permission, onchange: null })
+      : originalQuery(parameters);
+}
+
+// An empty plugin list is a strong headless signal
+if (navigator.plugins.length === 0) {
+  Object.defineProperty(Navigator.prototype, 'plugins', {
+    get: () => [
+      { name: 'PDF Viewer', filename: 'internal-pdf-viewer', description: 'Portable Document Format' },
+      { name: 'Chrome PDF Viewer', filename: 'internal-pdf-viewer', description: 'Portable Document Format' },
+    ],
+    configurable: true,
+  });
+}
@@ -0,0 +1,71 @@
+//! Blocking of analytics and tracking domains.
+//! Trackers add fingerprinting surface and bandwidth without helping a solve, so the browser can
+//! be told not to load them (via CDP `Network.setBlockedURLs`). Challenge providers' own domains
+//! are never blocked, since challenges fail without them.
+
+use anyhow::Result;
+use log::warn;
+
+/// Well-known analytics and tracking domains blocked by `BLOCK_TRACKERS`.
+pub const DEFAULT_TRACKER_DOMAINS: &[&str] = &[
+    "google-analytics.com",
+    "analytics.google.com",
+    "googletagmanager.com",
+    "googletagservices.com",
+    "doubleclick.net",
+    "googlesyndication.com",
+    "connect.facebook.net",
+    "hotjar.com",
+    "clarity.ms",
+    "segment.io",
+    "segment.com",
+    "mixpanel.com",
+    "amplitude.com",
+    "scorecardresearch.com",
+    "quantserve.com",
+    "criteo.com",
+    "taboola.com",
+    "outbrain.com",
+];
+
+/// Domains that serve or report on challenges. Blocklist entries covering them are ignored.
+const PROTECTED_DOMAINS: &[&str] = &["cloudflare.com", "cloudflareinsights.com", "ddos-guard.net"];
+
+/// Read a blocklist file: one domain per line, with empty lines and `#` comments ignored.
+pub fn load_blocklist(path: &str) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read tracker blocklist '{path}': {e}"))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|domain| domain.trim_start_matches("*.").trim_start_matches('.'))
+        .map(str::to_ascii_lowercase)
+        .collect())
+}
+
+/// Drop entries that would block a challenge provider, with a warning for each.
+pub fn without_protected(domains: Vec<String>) -> Vec<String> {
+    domains
+        .into_iter()
+        .filter(|domain| {
+            let protected = PROTECTED_DOMAINS.iter().any(|protected| {
+                domain == protected
+                    || domain.ends_with(&format!(".{protected}"))
+                    || protected.ends_with(&format!(".{domain}"))
+            });
+            if protected {
+                warn!("Not blocking '{domain}': it is needed to solve challenges");
+            }
+            !protected
+        })
+        .collect()
+}
+
+/// URL patterns for `Network.setBlockedURLs` matching each domain and its subdomains.
+pub fn url_patterns(domains: &[String]) -> Vec<String> {
+    domains
+        .iter()
+        .flat_map(|domain| [format!("*://{domain}/*"), format!("*://*.{domain}/*")])
+        .collect()
+}
@@ -0,0 +1,59 @@
+//! Generation of the browser's user agent.
+//! Cloudflare can tell the real Chrome version from the browser's behaviour, so a user agent
+//! claiming another version (or another browser) stands out. Once the installed Chrome's version
+//! is known, generated user agents claim that version.
+
+use anyhow::Result;
+use std::sync::OnceLock;
+
+/// Location of the Chrome binary in the container image.
+const CHROME_PATH: &str = "/usr/bin/google-chrome";
+
+/// Major version of the installed Chrome, set at startup if `MATCH_UA_TO_CHROME` is enabled.
+static CHROME_MAJOR_VERSION: OnceLock<u32> = OnceLock::new();
+
+/// Read the major version of the installed Chrome from `google-chrome --version`.
+pub fn detect_chrome_version() -> Result<u32> {
+    let output = std::process::Command::new(CHROME_PATH)
+        .arg("--version")
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run {CHROME_PATH} --version: {e}"))?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    // e.g. "Google Chrome 131.0.6778.85"
+    version
+        .split_whitespace()
+        .find(|part| part.starts_with(|c: char| c.is_ascii_digit()))
+        .and_then(|part| part.split('.').next())
+        .and_then(|major| major.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("Unexpected Chrome version output: {}", version.trim()))
+}
+
+/// Make generated user agents claim this Chrome major version.
+pub fn set_chrome_version(major: u32) {
+    let _ = CHROME_MAJOR_VERSION.set(major);
+}
+
+/// A random user agent: a Chrome one claiming the installed version if it is known, otherwise
+/// any browser's.
+pub fn generate() -> String {
+    match CHROME_MAJOR_VERSION.get() {
+        Some(major) => with_chrome_version(ua_generator::ua::spoof_chrome_ua(), *major),
+        None => ua_generator::ua::spoof_ua().to_string(),
+    }
+}
+
+/// Replace the version in a Chrome user agent with `major` in Chrome's reduced form
+/// (`Chrome/131.0.0.0`), as real Chrome sends it.
+fn with_chrome_version(user_agent: &str, major: u32) -> String {
+    let Some(start) = user_agent.find("Chrome/").map(|idx| idx + "Chrome/".len()) else {
+        return user_agent.to_string();
+    };
+    let end = user_agent[start..]
+        .find(' ')
+        .map_or(user_agent.len(), |idx| start + idx);
+    format!(
+        "{}{major}.0.0.0{}",
+        &user_agent[..start],
+        &user_agent[end..]
+    )
+}
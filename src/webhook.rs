@@ -0,0 +1,96 @@
+//! Fire-and-forget webhook notifications for finished solve requests and a low Scrappey balance.
+//! Lets monitoring pipelines follow solves asynchronously; delivery failures are only logged.
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How long a webhook delivery may take before it's abandoned.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Which engine produced a solution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Engine {
+    Browser,
+    Scrappey,
+    Cache,
+}
+
+/// Payload POSTed to the webhook for each solve request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SolveEvent {
+    pub request_id: String,
+    pub cmd: String,
+    pub url: Option<String>,
+    /// `ok` or `error`, as in the API response.
+    pub status: String,
+    pub message: String,
+    /// HTTP status of the solved page, if solved.
+    pub http_status: Option<u16>,
+    pub engine: Option<Engine>,
+    pub duration_ms: u64,
+}
+
+/// Payload POSTed to the webhook when the Scrappey balance drops below the alert threshold.
+/// Told apart from solve events by its `event` field, which those don't have.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LowBalanceEvent {
+    /// Always `lowBalance`.
+    pub event: String,
+    pub balance: f64,
+    pub threshold: f64,
+    /// Time of the balance reading, in milliseconds since the Unix epoch.
+    pub timestamp: i64,
+}
+
+/// Client for the configured webhook endpoint.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Deliver an event in the background, without waiting for the result.
+    pub fn notify(&self, event: SolveEvent) {
+        let subject = format!("request {}", event.request_id);
+        self.deliver(event, subject);
+    }
+
+    /// Deliver a low balance alert in the background, without waiting for the result.
+    pub fn notify_low_balance(&self, event: LowBalanceEvent) {
+        self.deliver(event, "the low Scrappey balance".to_string());
+    }
+
+    /// POST a payload in the background, logging the outcome as being about `subject`.
+    fn deliver<T: Serialize + Send + 'static>(&self, payload: T, subject: String) {
+        let webhook = self.clone();
+        tokio::spawn(async move {
+            let result = webhook
+                .client
+                .post(&webhook.url)
+                .json(&payload)
+                .timeout(WEBHOOK_TIMEOUT)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            match result {
+                Ok(_) => debug!("Delivered webhook for {subject}"),
+                Err(e) => warn!(
+                    "Failed to deliver webhook for {subject}: {}",
+                    e.without_url()
+                ),
+            }
+        });
+    }
+}